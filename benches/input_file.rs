@@ -0,0 +1,133 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, Stdio},
+    time::Instant,
+};
+
+use clap::Parser;
+
+//@@の入力ファイルをディスクに置いたときとmemfdにしたときで、同じ入力を同じ回数実行して速さを比べる
+//ビルドしたlibafl-sampleのreplayを、input_fileだけ変えたキャンペーンファイルで実行する
+#[derive(Debug, Parser)]
+struct Options {
+    /// Input file, or a directory of inputs to run in turn
+    #[arg(short = 'i', value_name = "path")]
+    input: PathBuf,
+
+    /// Number of runs to time for each input file kind
+    #[arg(long, value_name = "n", default_value_t = 10000)]
+    execs: usize,
+
+    /// Target program and its arguments; must contain @@
+    #[arg(last = true, required = true, value_name = "target")]
+    command: Vec<OsString>,
+}
+
+const KINDS: [&str; 2] = ["disk", "memfd"];
+
+fn main() -> ExitCode {
+    //cargo benchは引数の最後に--benchを付ける
+    //cargo test --benchesでは付かないので、何もしない
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    if args.last().is_none_or(|arg| arg != "--bench") {
+        return ExitCode::SUCCESS;
+    }
+    args.pop();
+
+    let options = Options::parse_from(args);
+    match bench(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn bench(options: &Options) -> Result<(), String> {
+    if !options.command.iter().any(|arg| arg == "@@") {
+        return Err("the target command has no @@ input file to compare".to_string());
+    }
+
+    let work_dir = std::env::temp_dir().join(format!("libafl-sample-bench-{}", std::process::id()));
+    let result = prepare(options, &work_dir).and_then(|inputs| {
+        let mut baseline = None;
+        for kind in KINDS {
+            let execs_per_sec = measure(options, &work_dir, &inputs, kind)?;
+            match baseline {
+                Some(baseline) => println!(
+                    "{kind}: {execs_per_sec:.0} execs/sec ({:+.1}% vs disk)",
+                    (execs_per_sec / baseline - 1.0) * 100.0
+                ),
+                None => println!("{kind}: {execs_per_sec:.0} execs/sec"),
+            }
+            baseline.get_or_insert(execs_per_sec);
+        }
+        Ok(())
+    });
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+//replayは入力を1回ずつ実行するので、execsの数だけ入力をコピーしておく
+fn prepare(options: &Options, work_dir: &Path) -> Result<PathBuf, String> {
+    let files = if options.input.is_dir() {
+        let mut files = fs::read_dir(&options.input)
+            .map_err(|err| format!("{}: {err}", options.input.display()))?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && !path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    } else {
+        vec![options.input.clone()]
+    };
+    if files.is_empty() {
+        return Err(format!("no inputs in {}", options.input.display()));
+    }
+
+    let inputs = work_dir.join("inputs");
+    fs::create_dir_all(&inputs).map_err(|err| err.to_string())?;
+    for (index, file) in files.iter().cycle().take(options.execs).enumerate() {
+        fs::copy(file, inputs.join(format!("{index:06}")))
+            .map_err(|err| format!("{}: {err}", file.display()))?;
+    }
+    Ok(inputs)
+}
+
+//forkserverの起動やページキャッシュの影響は、execsが十分に多ければ無視できる
+#[allow(clippy::cast_precision_loss)]
+fn measure(options: &Options, work_dir: &Path, inputs: &Path, kind: &str) -> Result<f64, String> {
+    let campaign = work_dir.join(format!("{kind}.toml"));
+    fs::write(&campaign, format!("[target]\ninput_file = \"{kind}\"\n"))
+        .map_err(|err| err.to_string())?;
+
+    let start = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_libafl-sample"))
+        .arg("replay")
+        .arg("-i")
+        .arg(inputs)
+        .arg("--campaign")
+        .arg(&campaign)
+        .arg("--")
+        .args(&options.command)
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|err| err.to_string())?;
+    let elapsed = start.elapsed();
+
+    if !status.success() {
+        return Err(format!(
+            "replay with input_file = \"{kind}\" failed: {status}"
+        ));
+    }
+    Ok(options.execs as f64 / elapsed.as_secs_f64())
+}
@@ -0,0 +1,413 @@
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    fmt::Write,
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use libafl::{
+    corpus::{Corpus, CorpusId, HasTestcase, Testcase},
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::{Input, UsesInput},
+    observers::ObserversTuple,
+    state::{HasClientPerfMonitor, HasCorpus, HasMetadata, HasStartTime},
+    Error,
+};
+use libafl_bolts::{current_time, impl_serdeany, Named};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    executor::{SignalObserver, SIGNAL_NAME},
+    mutators::AppliedMutations,
+    sync::SyncSource,
+};
+
+//AFL++のファイル名に入れる、テストケースを見つけたときの状況
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Origin {
+    //キャンペーンを始めてからの時間
+    pub time: Duration,
+    //mutationを通らずに見つかったもの(シードやCmpLogのステージ)はNone
+    pub op: Option<String>,
+    //重ねたmutationの数
+    pub rep: usize,
+    pub signal: Option<i32>,
+    //AFL++のインスタンスから取り込んだもの
+    pub sync: Option<SyncSource>,
+    //シードのファイル名で、afl-fuzzと同じくorig:として名前に残す
+    pub orig: Option<String>,
+}
+
+impl_serdeany!(Origin);
+
+//シードのファイルを読んでいる間、stateに置いておきOriginに写す
+//再開時に_resumeから読み直すときは、前回のqueueでの名前になる
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedSource {
+    pub name: String,
+}
+
+impl_serdeany!(SeedSource);
+
+impl Origin {
+    //libaflのhavocはcrossoverも含むので、afl-fuzzのspliceとは分けずにhavocとする
+    pub fn capture<S, OT>(state: &S, observers: &OT) -> Self
+    where
+        S: UsesInput + HasMetadata + HasStartTime,
+        OT: ObserversTuple<S>,
+    {
+        //取り込んだものは、最後にmutationした入力とは関係がない
+        let sync = state.metadata_map().get::<SyncSource>().cloned();
+        let rep = state
+            .metadata_map()
+            .get::<AppliedMutations>()
+            .filter(|_| sync.is_none())
+            .map_or(0, |applied| applied.ids.len());
+
+        Self {
+            time: current_time().saturating_sub(*state.start_time()),
+            op: (rep != 0).then(|| "havoc".to_string()),
+            rep,
+            signal: observers
+                .match_name::<SignalObserver>(SIGNAL_NAME)
+                .and_then(SignalObserver::signal),
+            sync,
+            orig: state
+                .metadata_map()
+                .get::<SeedSource>()
+                .map(|seed| original_name(&seed.name).to_string()),
+        }
+    }
+}
+
+//前回のqueueの名前なら、afl-fuzzと同じくorig:の後ろの元のファイル名を取り出す
+fn original_name(name: &str) -> &str {
+    name.split_once(",orig:").map_or(name, |(_, orig)| orig)
+}
+
+//id:000123,...のファイル名からidを取り出す
+pub fn parse_id(name: &str) -> Option<usize> {
+    name.strip_prefix("id:")?.get(..6)?.parse().ok()
+}
+
+//src:000045のidを取り出す、spliceのsrc:000045+000067なら最初のもの
+//取り込んだもののsrcは相手のqueueのidなので、親にはならない
+fn parse_src(name: &str) -> Option<usize> {
+    if name.contains(",sync:") {
+        return None;
+    }
+    let (_, src) = name.split_once(",src:")?;
+    src.get(..6)?.parse().ok()
+}
+
+//ディレクトリに残っている一番大きいidの次
+pub fn next_id(dir: &Path) -> Result<usize, Error> {
+    let mut next_id = 0;
+    for entry in fs::read_dir(dir)? {
+        if let Some(id) = entry?.file_name().to_str().and_then(parse_id) {
+            next_id = next_id.max(id + 1);
+        }
+    }
+    Ok(next_id)
+}
+
+//コーパスのfeedbackにfeedback_or!でつなぎ、queueのテストケースにOriginを付ける
+//TimeFeedbackと同じく、条件判定には寄与しない
+#[derive(Debug, Default)]
+pub struct OriginFeedback;
+
+impl OriginFeedback {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for OriginFeedback {
+    fn name(&self) -> &str {
+        "OriginFeedback"
+    }
+}
+
+impl<S> Feedback<S> for OriginFeedback
+where
+    S: UsesInput + HasClientPerfMonitor + HasCorpus + HasMetadata + HasStartTime,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(false)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        //親はスケジューラがcorpusに追加した後で付けるので、名前を決める前に付けておく
+        //solutionにはfuzzerが付けている
+        testcase.set_parent_id_optional(*state.corpus().current());
+        testcase.add_metadata(Origin::capture(state, observers));
+
+        //afl-fuzzと同じく、シードの名前が振るidと一致すれば、srcの親も含めてその名前をそのまま使う
+        //_resumeから読み直した前回のqueueは、順に追加されるのでidが変わらない
+        if let Some(seed) = state.metadata_map().get::<SeedSource>() {
+            if parse_id(&seed.name) == Some(state.corpus().count()) {
+                testcase.set_parent_id_optional(parse_src(&seed.name).map(CorpusId::from));
+                *testcase.filename_mut() = Some(seed.name.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+//追加するテストケースに、AFL++と同じid:000123,src:000045,time:...,execs:...,op:havoc,rep:4の名前を付けるcorpus
+//afl-covやafl-plotなど、AFL++のツールが出力ディレクトリをそのまま読める
+//idはディレクトリごとの通し番号で、queueは毎回空から始めるのでCorpusIdと一致する
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "C: Serialize + DeserializeOwned")]
+pub struct AflCorpus<C> {
+    inner: C,
+    next_id: usize,
+    //crashes/では、afl-fuzzと同じくシグナルを名前に入れる
+    signal: bool,
+}
+
+impl<C> AflCorpus<C> {
+    //再開したときに前回のファイルを上書きしないように、残っている一番大きいidの次から振る
+    pub fn new(inner: C, dir: &Path, signal: bool) -> Result<Self, Error> {
+        Ok(Self {
+            inner,
+            next_id: next_id(dir)?,
+            signal,
+        })
+    }
+
+    fn name<I: Input>(&self, testcase: &Testcase<I>) -> String {
+        let origin = testcase.metadata_map().get::<Origin>();
+
+        let mut name = format!("id:{:06}", self.next_id);
+        if let Some(signal) = origin
+            .and_then(|origin| origin.signal)
+            .filter(|_| self.signal)
+        {
+            let _ = write!(name, ",sig:{signal:02}");
+        }
+        //取り込んだものは、afl-fuzzと同じく相手のqueueのidをsrcにする
+        if let Some(sync) = origin.and_then(|origin| origin.sync.as_ref()) {
+            let _ = write!(name, ",sync:{}", sync.peer);
+            if let Some(id) = sync.id {
+                let _ = write!(name, ",src:{id:06}");
+            }
+        } else if let Some(parent) = testcase.parent_id() {
+            let _ = write!(name, ",src:{:06}", usize::from(parent));
+        }
+        if let Some(origin) = origin {
+            let _ = write!(name, ",time:{}", origin.time.as_millis());
+        }
+        let _ = write!(name, ",execs:{}", testcase.executions());
+        if let Some(op) = origin.and_then(|origin| origin.op.as_ref()) {
+            let _ = write!(
+                name,
+                ",op:{op},rep:{}",
+                origin.map_or(0, |origin| origin.rep)
+            );
+        }
+        if let Some(orig) = origin.and_then(|origin| origin.orig.as_ref()) {
+            let _ = write!(name, ",orig:{orig}");
+        }
+        name
+    }
+}
+
+impl<C> UsesInput for AflCorpus<C>
+where
+    C: Corpus,
+{
+    type Input = C::Input;
+}
+
+impl<C> Corpus for AflCorpus<C>
+where
+    C: Corpus,
+{
+    fn count(&self) -> usize {
+        self.inner.count()
+    }
+
+    fn add(&mut self, mut testcase: Testcase<Self::Input>) -> Result<CorpusId, Error> {
+        if testcase.filename().is_none() {
+            *testcase.filename_mut() = Some(self.name(&testcase));
+        }
+        self.next_id += 1;
+        self.inner.add(testcase)
+    }
+
+    fn replace(
+        &mut self,
+        id: CorpusId,
+        testcase: Testcase<Self::Input>,
+    ) -> Result<Testcase<Self::Input>, Error> {
+        self.inner.replace(id, testcase)
+    }
+
+    fn remove(&mut self, id: CorpusId) -> Result<Testcase<Self::Input>, Error> {
+        self.inner.remove(id)
+    }
+
+    fn get(&self, id: CorpusId) -> Result<&RefCell<Testcase<Self::Input>>, Error> {
+        self.inner.get(id)
+    }
+
+    fn current(&self) -> &Option<CorpusId> {
+        self.inner.current()
+    }
+
+    fn current_mut(&mut self) -> &mut Option<CorpusId> {
+        self.inner.current_mut()
+    }
+
+    fn next(&self, id: CorpusId) -> Option<CorpusId> {
+        self.inner.next(id)
+    }
+
+    fn prev(&self, id: CorpusId) -> Option<CorpusId> {
+        self.inner.prev(id)
+    }
+
+    fn first(&self) -> Option<CorpusId> {
+        self.inner.first()
+    }
+
+    fn last(&self) -> Option<CorpusId> {
+        self.inner.last()
+    }
+
+    fn nth(&self, nth: usize) -> CorpusId {
+        self.inner.nth(nth)
+    }
+
+    fn load_input_into(&self, testcase: &mut Testcase<Self::Input>) -> Result<(), Error> {
+        self.inner.load_input_into(testcase)
+    }
+
+    fn store_input_from(&self, testcase: &Testcase<Self::Input>) -> Result<(), Error> {
+        self.inner.store_input_from(testcase)
+    }
+}
+
+impl<C> HasTestcase for AflCorpus<C>
+where
+    C: Corpus,
+{
+    fn testcase(&self, id: CorpusId) -> Result<Ref<'_, Testcase<Self::Input>>, Error> {
+        Ok(self.get(id)?.borrow())
+    }
+
+    fn testcase_mut(&self, id: CorpusId) -> Result<RefMut<'_, Testcase<Self::Input>>, Error> {
+        Ok(self.get(id)?.borrow_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::{
+        corpus::InMemoryCorpus, feedbacks::ConstFeedback, inputs::BytesInput, state::StdState,
+    };
+    use libafl_bolts::rands::StdRand;
+
+    use super::*;
+    use crate::runner::RunnerState;
+
+    fn new_state() -> RunnerState {
+        StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        )
+        .unwrap()
+    }
+
+    //シードを読んでいるときと同じく、stateにSeedSourceを置いてOriginFeedbackを通す
+    fn seed_testcase(state: &mut RunnerState, name: &str) -> Testcase<BytesInput> {
+        let mut testcase = Testcase::new(BytesInput::new(b"seed".to_vec()));
+        state.add_metadata(SeedSource {
+            name: name.to_string(),
+        });
+        OriginFeedback::new()
+            .append_metadata(state, &(), &mut testcase)
+            .unwrap();
+        let _ = state.metadata_map_mut().remove::<SeedSource>();
+        testcase
+    }
+
+    #[test]
+    fn seed_name_has_orig() {
+        let mut state = new_state();
+        let testcase = seed_testcase(&mut state, "png-header");
+        let corpus = AflCorpus {
+            inner: InMemoryCorpus::<BytesInput>::new(),
+            next_id: 0,
+            signal: false,
+        };
+
+        assert!(testcase.filename().is_none());
+        let name = corpus.name(&testcase);
+        assert!(name.starts_with("id:000000,time:"), "{name}");
+        assert!(name.ends_with(",execs:0,orig:png-header"), "{name}");
+    }
+
+    //前回のqueueの名前は、idが一致すればsrcの親と一緒に引き継ぐ
+    #[test]
+    fn resumed_name_keeps_lineage() {
+        let mut state = new_state();
+        let testcase = seed_testcase(&mut state, "id:000000,time:12,execs:1,orig:a");
+        assert_eq!(
+            testcase.filename().as_deref(),
+            Some("id:000000,time:12,execs:1,orig:a")
+        );
+        assert!(testcase.parent_id().is_none());
+        state.corpus_mut().add(testcase).unwrap();
+
+        let name = "id:000001,src:000000,time:40,execs:4,op:havoc,rep:2";
+        let testcase = seed_testcase(&mut state, name);
+        assert_eq!(testcase.filename().as_deref(), Some(name));
+        assert_eq!(testcase.parent_id(), Some(CorpusId::from(0_usize)));
+    }
+
+    //idがずれたら新しい名前にして、元のファイル名だけをorig:に残す
+    #[test]
+    fn shifted_name_keeps_only_orig() {
+        let mut state = new_state();
+        let testcase = seed_testcase(&mut state, "id:000003,time:40,execs:4,orig:a");
+
+        assert!(testcase.filename().is_none());
+        assert!(testcase.parent_id().is_none());
+        let origin = testcase.metadata_map().get::<Origin>().unwrap();
+        assert_eq!(origin.orig.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn src_of_synced_name_is_not_parent() {
+        assert_eq!(parse_src("id:000004,src:000001+000002,time:1"), Some(1));
+        assert_eq!(parse_src("id:000004,sync:main,src:000007,time:1"), None);
+        assert_eq!(parse_src("id:000000,time:0,execs:0,orig:a"), None);
+    }
+}
@@ -0,0 +1,121 @@
+use std::marker::PhantomData;
+
+use libafl::{
+    corpus::CorpusId,
+    events::{Event, EventFirer},
+    feedbacks::MapFeedbackMetadata,
+    monitors::UserStats,
+    stages::{calibrate::UnstableEntriesMetadata, Stage},
+    state::{HasMetadata, HasNamedMetadata, UsesState},
+    Error,
+};
+
+use crate::uniqueness;
+
+//新しいコーパスを初めて選んだときに何度か実行し、実行時間と、実行ごとに変わるエッジを調べる
+//CalibrationStageは変わったエッジのhistoryを最大値にするので、そのエッジは新しいカバレッジにならなくなる
+//solutionのhistoryにも同じエッジを反映し、安定度(変わらないエッジの割合)をモニタに送る
+//calibrationがNoneなら何もしない
+pub struct StabilityStage<C, E, EM, Z> {
+    calibration: Option<C>,
+    //MaxMapFeedbackの名前で、historyはこの名前で保存されている
+    map_name: String,
+    track_stability: bool,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<C, E, EM, Z> StabilityStage<C, E, EM, Z> {
+    pub fn new(calibration: Option<C>, map_name: &str, track_stability: bool) -> Self {
+        Self {
+            calibration,
+            map_name: map_name.to_string(),
+            track_stability,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<C, E, EM, Z> UsesState for StabilityStage<C, E, EM, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<C, E, EM, Z> Stage<E, EM, Z> for StabilityStage<C, E, EM, Z>
+where
+    C: Stage<E, EM, Z, State = E::State>,
+    E: UsesState,
+    E::State: HasMetadata + HasNamedMetadata,
+    EM: EventFirer<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+        corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        let Some(calibration) = &mut self.calibration else {
+            return Ok(());
+        };
+        calibration.perform(fuzzer, executor, state, manager, corpus_idx)?;
+
+        if !self.track_stability {
+            return Ok(());
+        }
+
+        //一度も変わっていなければ、メタデータはまだ作られていない
+        let unstable = state
+            .metadata_map()
+            .get::<UnstableEntriesMetadata>()
+            .map(|metadata| metadata.unstable_entries().clone())
+            .unwrap_or_default();
+
+        //solutionのhistoryは、最初のsolutionが見つかるまで空のままなので、足りなければ伸ばす
+        for name in [
+            uniqueness::CRASH_HISTORY_NAME,
+            uniqueness::HANG_HISTORY_NAME,
+        ] {
+            let Some(metadata) = state
+                .named_metadata_map_mut()
+                .get_mut::<MapFeedbackMetadata<u8>>(name)
+            else {
+                continue;
+            };
+            for index in &unstable {
+                if metadata.history_map.len() <= *index {
+                    metadata.history_map.resize(index + 1, 0);
+                }
+                metadata.history_map[*index] = u8::MAX;
+            }
+        }
+
+        //マップ全体ではなく、これまでに見たエッジに対する割合にする(afl-fuzzのstabilityと同じ)
+        let covered = state
+            .named_metadata_map()
+            .get::<MapFeedbackMetadata<u8>>(&self.map_name)
+            .map_or(0, |metadata| {
+                metadata
+                    .history_map
+                    .iter()
+                    .filter(|history| **history != 0)
+                    .count()
+            });
+
+        //CalibrationStageもマップ全体に対する割合を送るので、同じ名前で上書きする
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: "stability".to_string(),
+                value: UserStats::Ratio(
+                    covered.saturating_sub(unstable.len()) as u64,
+                    covered as u64,
+                ),
+                phantom: PhantomData,
+            },
+        )
+    }
+}
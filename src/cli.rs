@@ -0,0 +1,252 @@
+use std::{ffi::OsString, path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+
+//afl-fuzzと同じオプションで起動できるようにする
+//既存のAFL++用のスクリプトをそのまま流用するため、短いオプション名はafl-fuzzに合わせる
+//キャンペーンファイルと併用した場合は、コマンドラインの指定が優先される
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "afl-fuzz compatible forkserver fuzzer built on LibAFL",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Options {
+    //サブコマンドがなければ、ファジングを行う
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Input directory with the initial test cases
+    #[arg(short = 'i', value_name = "dir", required_unless_present = "campaign")]
+    pub input: Option<PathBuf>,
+
+    /// Output directory for fuzzer findings
+    #[arg(short = 'o', value_name = "dir", required_unless_present = "campaign")]
+    pub output: Option<PathBuf>,
+
+    /// Fuzzer dictionary file or directory (may be given multiple times)
+    #[arg(short = 'x', value_name = "dict")]
+    pub dictionaries: Vec<PathBuf>,
+
+    /// CmpLog-instrumented build of the target for input-to-state mutations
+    #[arg(short = 'c', value_name = "program")]
+    pub cmplog: Option<PathBuf>,
+
+    /// CPU cores to run clients on, e.g. "0-3", "0,2,4" or "all"; each client writes to <output>/client_<core>
+    #[arg(long, value_name = "cores")]
+    pub cores: Option<String>,
+
+    /// Port of the LLMP broker used with --cores [default: 1337]
+    #[arg(long, value_name = "port", requires = "cores")]
+    pub broker_port: Option<u16>,
+
+    #[command(flatten)]
+    pub target: TargetOptions,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Minimize a crashing input while preserving the crash (like afl-tmin)
+    Tmin(TminOptions),
+    /// Select the smallest set of inputs with the same coverage (like afl-cmin)
+    Cmin(CminOptions),
+    /// Run inputs once and report how the target exited
+    Replay(ReplayOptions),
+    /// Write the coverage map of inputs as index:class tuples (like afl-showmap)
+    Showmap(ShowmapOptions),
+}
+
+//ターゲットの実行に関するオプションで、ファジングと各サブコマンドで共通
+#[derive(Debug, Args)]
+pub struct TargetOptions {
+    /// Campaign definition file (TOML)
+    #[arg(long, value_name = "file")]
+    pub campaign: Option<PathBuf>,
+
+    /// Timeout for each run in milliseconds, or "auto" to derive it from the seeds [default: 5000]
+    #[arg(short = 't', value_name = "msec", value_parser = parse_timeout)]
+    pub timeout: Option<TimeoutArg>,
+
+    /// Re-run timed out inputs with this timeout in milliseconds before reporting a hang
+    #[arg(long, value_name = "msec", env = "AFL_HANG_TMOUT", value_parser = parse_msec)]
+    pub hang_timeout: Option<Duration>,
+
+    /// Memory limit for the target in megabytes, or "none" [default: none]
+    #[arg(short = 'm', value_name = "megs", value_parser = parse_memory_limit)]
+    pub memory_limit: Option<u64>,
+
+    /// Maximum size of the coverage map shared with the target [default: the size the target reports, or 65536]
+    #[arg(long, value_name = "bytes", env = "AFL_MAP_SIZE")]
+    pub map_size: Option<usize>,
+
+    /// Target program and its arguments; @@ is replaced with the input file
+    #[arg(
+        value_name = "target",
+        required_unless_present = "campaign",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    pub command: Vec<OsString>,
+}
+
+//afl-tminと同じく、-iに元の入力ファイル、-oに最小化した入力の出力先を指定する
+#[derive(Debug, Args)]
+pub struct TminOptions {
+    /// Crashing input to minimize, e.g. a file from crashes/
+    #[arg(short = 'i', value_name = "file")]
+    pub input: PathBuf,
+
+    /// File to write the minimized input to
+    #[arg(short = 'o', value_name = "file")]
+    pub output: PathBuf,
+
+    /// Also require the same ASan stack hash as the original crash
+    #[arg(long)]
+    pub stack_hash: bool,
+
+    /// Stop after this many mutations in a row without a smaller crash; the count restarts whenever one is found
+    #[arg(long, value_name = "n", default_value_t = 1024)]
+    pub runs: usize,
+
+    #[command(flatten)]
+    pub target: TargetOptions,
+}
+
+//afl-cminと同じく、-iのディレクトリから選んだ入力を-oのディレクトリにコピーする
+#[derive(Debug, Args)]
+pub struct CminOptions {
+    /// Directory with the inputs to minimize
+    #[arg(short = 'i', value_name = "dir")]
+    pub input: PathBuf,
+
+    /// Directory to copy the selected inputs to; must not exist or be empty
+    #[arg(short = 'o', value_name = "dir")]
+    pub output: PathBuf,
+
+    /// Keep inputs that crash or time out instead of skipping them
+    #[arg(long)]
+    pub keep_crashes: bool,
+
+    #[command(flatten)]
+    pub target: TargetOptions,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayOptions {
+    /// Input file, or a directory of inputs such as crashes/
+    #[arg(short = 'i', value_name = "path")]
+    pub input: PathBuf,
+
+    #[command(flatten)]
+    pub target: TargetOptions,
+}
+
+//-iがファイルなら-oもファイル、ディレクトリなら-oもディレクトリになる
+#[derive(Debug, Args)]
+pub struct ShowmapOptions {
+    /// Input file, or a directory of inputs such as queue/
+    #[arg(short = 'i', value_name = "path")]
+    pub input: PathBuf,
+
+    /// File (or directory, for a directory input) to write the maps to
+    #[arg(short = 'o', value_name = "path")]
+    pub output: PathBuf,
+
+    /// Also write the union of all maps to this file
+    #[arg(long, value_name = "file")]
+    pub union: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub target: TargetOptions,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TimeoutArg {
+    Fixed(Duration),
+    //シードの実行時間から求める
+    Auto,
+}
+
+//afl-fuzzの-tは末尾の+を許容する(タイムアウトするシードを無視する指定)
+//シードのタイムアウトで停止することはないので、+は読み飛ばす
+fn parse_timeout(arg: &str) -> Result<TimeoutArg, String> {
+    if arg == "auto" {
+        return Ok(TimeoutArg::Auto);
+    }
+
+    parse_msec(arg.trim_end_matches('+')).map(TimeoutArg::Fixed)
+}
+
+fn parse_msec(arg: &str) -> Result<Duration, String> {
+    let msec = arg
+        .parse::<u64>()
+        .map_err(|err| format!("invalid timeout {arg:?}: {err}"))?;
+
+    if msec == 0 {
+        return Err("timeout must be greater than 0".to_string());
+    }
+
+    Ok(Duration::from_millis(msec))
+}
+
+//0は制限なしを表す(libaflのConfigTarget::setlimitと同じ扱い)
+fn parse_memory_limit(arg: &str) -> Result<u64, String> {
+    if arg == "none" {
+        return Ok(0);
+    }
+
+    arg.parse::<u64>()
+        .map_err(|err| format!("invalid memory limit {arg:?}: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_accepts_auto_and_trailing_plus() {
+        assert!(matches!(parse_timeout("auto"), Ok(TimeoutArg::Auto)));
+        assert!(matches!(
+            parse_timeout("500"),
+            Ok(TimeoutArg::Fixed(timeout)) if timeout == Duration::from_millis(500)
+        ));
+        assert!(matches!(
+            parse_timeout("500+"),
+            Ok(TimeoutArg::Fixed(timeout)) if timeout == Duration::from_millis(500)
+        ));
+    }
+
+    #[test]
+    fn timeout_rejects_zero_and_garbage() {
+        assert_eq!(
+            parse_msec("0").unwrap_err(),
+            "timeout must be greater than 0"
+        );
+        assert!(parse_timeout("0+").is_err());
+        assert!(parse_timeout("fast").unwrap_err().contains("\"fast\""));
+        assert!(parse_timeout("-5").is_err());
+    }
+
+    #[test]
+    fn memory_limit_accepts_none() {
+        assert_eq!(parse_memory_limit("none"), Ok(0));
+        assert_eq!(parse_memory_limit("50"), Ok(50));
+        assert!(parse_memory_limit("50M").unwrap_err().contains("\"50M\""));
+    }
+
+    //不正な値は、どのオプションのものかが分かるエラーになる
+    #[test]
+    fn errors_name_the_option() {
+        let err = Options::try_parse_from(["libafl-sample", "-i", "in", "-o", "out", "-t", "0"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("-t <msec>"), "{err}");
+        assert!(err.contains("timeout must be greater than 0"), "{err}");
+
+        let err = Options::try_parse_from(["libafl-sample", "-i", "in", "-o", "out", "-m", "lots"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("-m <megs>"), "{err}");
+    }
+}
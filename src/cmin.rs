@@ -0,0 +1,163 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use libafl::{
+    executors::ExitKind,
+    inputs::{BytesInput, Input},
+    Error,
+};
+
+use crate::{
+    cli::CminOptions,
+    config::ToolConfig,
+    runner::{self, Runner, WorkDir},
+};
+
+//HitcountsMapObserverで分類した後の(マップのインデックス, バケット)
+//同じエッジでもバケットが違えば別のタプルとして扱うので、ヒット回数の違いも残る
+type Tuple = (usize, u8);
+
+//afl-cminと同じ貪欲法で、全入力のタプルの和集合を保つ最小限の入力を選ぶ
+//libaflのMapCorpusMinimizerはz3が必要なので使わない
+pub fn cmin(config: &ToolConfig, options: &CminOptions) -> Result<(), Error> {
+    if !options.input.is_dir() {
+        return Err(Error::illegal_argument(format!(
+            "{} is not a directory",
+            options.input.display()
+        )));
+    }
+
+    //既にある入力と混ざると、どれが選ばれたものか分からなくなる
+    if fs::read_dir(&options.output).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(Error::illegal_argument(format!(
+            "{} is not empty",
+            options.output.display()
+        )));
+    }
+
+    //同じタプルを持つ入力の中では、小さいものを優先する
+    let mut files = runner::input_files(&options.input)?
+        .into_iter()
+        .map(|path| Ok((fs::metadata(&path)?.len(), path)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    files.sort();
+
+    let work_dir = WorkDir::new("cmin")?;
+    let mut shmem = runner::coverage_shmem(config, &work_dir)?;
+    let mut runner = Runner::new(config, &mut shmem, &work_dir)?;
+
+    let mut traces: Vec<(PathBuf, Vec<Tuple>)> = Vec::new();
+    let mut skipped = 0;
+
+    for (_, path) in files {
+        let input = BytesInput::from_file(&path)?;
+        let execution = runner.run(&input)?;
+
+        //クラッシュやタイムアウトする入力は、シードにするとファジングの邪魔になる
+        if execution.exit_kind != ExitKind::Ok && !options.keep_crashes {
+            skipped += 1;
+            continue;
+        }
+        traces.push((path, execution.coverage));
+    }
+
+    let coverages = traces
+        .iter()
+        .map(|(_, coverage)| coverage.as_slice())
+        .collect::<Vec<_>>();
+    let (selected, covered) = select(&coverages);
+
+    fs::create_dir_all(&options.output)?;
+    for index in &selected {
+        let path = &traces[*index].0;
+        let name = path
+            .file_name()
+            .ok_or_else(|| Error::illegal_argument(format!("{} has no name", path.display())))?;
+        fs::copy(path, options.output.join(name))?;
+    }
+
+    println!(
+        "selected {} of {} inputs covering {} tuples, skipped {} that crashed or timed out",
+        selected.len(),
+        traces.len(),
+        covered,
+        skipped
+    );
+    Ok(())
+}
+
+//coveragesは入力の小さい順で、選んだ入力のインデックスと、それらが持つタプルの数を返す
+//出現の少ないタプルから順に、それを持つ最小の入力を選ぶ
+fn select(coverages: &[&[Tuple]]) -> (BTreeSet<usize>, usize) {
+    let mut smallest: HashMap<Tuple, usize> = HashMap::new();
+    let mut counts: HashMap<Tuple, usize> = HashMap::new();
+    for (index, coverage) in coverages.iter().enumerate() {
+        for tuple in *coverage {
+            smallest.entry(*tuple).or_insert(index);
+            *counts.entry(*tuple).or_default() += 1;
+        }
+    }
+
+    let mut tuples = counts.into_iter().collect::<Vec<_>>();
+    tuples.sort_by_key(|(tuple, count)| (*count, *tuple));
+
+    let mut covered: HashSet<Tuple> = HashSet::new();
+    let mut selected = BTreeSet::new();
+    for (tuple, _) in tuples {
+        if covered.contains(&tuple) {
+            continue;
+        }
+        let index = smallest[&tuple];
+        selected.insert(index);
+        covered.extend(coverages[index].iter().copied());
+    }
+    (selected, covered.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //同じタプルを持つ入力の中では、一番小さいものだけが残る
+    #[test]
+    fn keeps_the_smallest_input_per_tuple() {
+        let small: &[Tuple] = &[(1, 1), (2, 1)];
+        let large: &[Tuple] = &[(1, 1), (2, 1)];
+        let (selected, covered) = select(&[small, large]);
+        assert_eq!(selected, BTreeSet::from([0]));
+        assert_eq!(covered, 2);
+    }
+
+    //珍しいタプルを持つ入力を先に選び、それで覆えたタプルのために小さい入力を足さない
+    #[test]
+    fn rare_tuples_decide_the_selection() {
+        let a: &[Tuple] = &[(1, 1)];
+        let b: &[Tuple] = &[(2, 1)];
+        let c: &[Tuple] = &[(1, 1), (2, 1), (3, 1)];
+        let (selected, covered) = select(&[a, b, c]);
+        assert_eq!(selected, BTreeSet::from([2]));
+        assert_eq!(covered, 3);
+    }
+
+    //同じエッジでもバケットが違えば別のタプルなので、ヒット回数の違う入力も残る
+    //全入力のタプルの和集合は保たれる
+    #[test]
+    fn keeps_the_union_of_all_tuples() {
+        let once: &[Tuple] = &[(1, 1), (4, 1)];
+        let twice: &[Tuple] = &[(1, 2)];
+        let more: &[Tuple] = &[(1, 1), (4, 1), (5, 8)];
+        let only_five: &[Tuple] = &[(5, 8)];
+        let (selected, covered) = select(&[once, twice, only_five, more]);
+        assert_eq!(selected, BTreeSet::from([0, 1, 2]));
+        assert_eq!(covered, 4);
+    }
+
+    #[test]
+    fn no_inputs_select_nothing() {
+        assert_eq!(select(&[]), (BTreeSet::new(), 0));
+        assert_eq!(select(&[&[]]), (BTreeSet::new(), 0));
+    }
+}
@@ -0,0 +1,129 @@
+use std::marker::PhantomData;
+
+use libafl::{
+    corpus::{Corpus, CorpusId},
+    observers::{AFLppCmpValuesMetadata, CmpValuesMetadata},
+    stages::{Stage, StagesTuple},
+    state::{HasCorpus, HasMetadata, UsesState},
+    Error,
+};
+use libafl_bolts::impl_serdeany;
+use serde::{Deserialize, Serialize};
+
+//CmpLogのバイナリで一度トレースしたテストケースに付ける
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CmpLogTraced;
+
+impl_serdeany!(CmpLogTraced);
+
+//colorization -> CmpLogのバイナリでのトレース -> RedQueen/I2Sのステージを、
+//テストケースごとに最初の1回だけ実行する(afl-fuzzの-cと同じ)
+//CmpLogのバイナリが指定されていなければ、何もしない
+pub struct CmpLogStage<E, EM, ST, Z> {
+    stages: Option<ST>,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, ST, Z> CmpLogStage<E, EM, ST, Z> {
+    pub fn new(stages: Option<ST>) -> Self {
+        Self {
+            stages,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, EM, ST, Z> UsesState for CmpLogStage<E, EM, ST, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, ST, Z> Stage<E, EM, Z> for CmpLogStage<E, EM, ST, Z>
+where
+    E: UsesState,
+    E::State: HasCorpus,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+    ST: StagesTuple<E, EM, E::State, Z>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+        corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        let Some(stages) = &mut self.stages else {
+            return Ok(());
+        };
+
+        {
+            let mut testcase = state.corpus().get(corpus_idx)?.borrow_mut();
+            if testcase.has_metadata::<CmpLogTraced>() {
+                return Ok(());
+            }
+            testcase.add_metadata(CmpLogTraced);
+        }
+
+        stages.perform_all(fuzzer, executor, state, manager, corpus_idx)
+    }
+}
+
+//I2SRandReplaceはCmpValuesMetadataを読むので、AFL++形式のトレース結果から作り直す
+//元の入力を実行したときの比較の値だけを使う
+pub struct CmpValuesStage<E, EM, Z> {
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, Z> CmpValuesStage<E, EM, Z> {
+    pub fn new() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, EM, Z> UsesState for CmpValuesStage<E, EM, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for CmpValuesStage<E, EM, Z>
+where
+    E: UsesState,
+    E::State: HasMetadata,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+        _corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        let list = state
+            .metadata_map()
+            .get::<AFLppCmpValuesMetadata>()
+            .map(|metadata| {
+                metadata
+                    .orig_cmpvals()
+                    .values()
+                    .flatten()
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut metadata = CmpValuesMetadata::new();
+        metadata.list = list;
+        state.add_metadata(metadata);
+        Ok(())
+    }
+}
@@ -0,0 +1,711 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use libafl::Error;
+use libafl_bolts::core_affinity::Cores;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{Options, TargetOptions, TimeoutArg};
+
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+//afl-fuzzと同じく、平均の5倍で、1秒を超えない
+const DEFAULT_AUTO_TIMEOUT_MULTIPLIER: f64 = 5.0;
+const DEFAULT_AUTO_TIMEOUT_MAX_MS: u64 = 1000;
+const DEFAULT_BROKER_PORT: u16 = 1337;
+const DEFAULT_SYNC_INTERVAL_SECS: u64 = 60;
+
+//キャンペーンファイル(TOML)をそのまま読み込んだもの
+//コマンドラインで上書きできる値はOptionにしておき、Config::loadで解決する
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Campaign {
+    target: TargetSection,
+    dirs: DirsSection,
+    fuzzer: FuzzerConfig,
+    feedback: FeedbackConfig,
+    objective: ObjectiveConfig,
+    launcher: LauncherSection,
+    sync: SyncSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TargetSection {
+    program: Option<PathBuf>,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    //afl-fuzzの-tと同じくミリ秒で指定する、"auto"ならシードの実行時間から求める
+    timeout: Option<TimeoutValue>,
+    //autoのとき、シードの平均の実行時間に掛ける倍率と、タイムアウトの上限(ミリ秒)
+    auto_timeout_multiplier: Option<f64>,
+    auto_timeout_max: Option<u64>,
+    //入力1KBごとにタイムアウトに足すミリ秒
+    timeout_per_kb: Option<u64>,
+    //afl-fuzzのAFL_HANG_TMOUTと同じく、タイムアウトした入力をこのミリ秒で実行し直す
+    hang_timeout: Option<u64>,
+    //afl-fuzzの-mと同じくMB単位、0は制限なし
+    memory_limit: Option<u64>,
+    map_size: Option<usize>,
+    //afl-fuzzの-cと同じく、AFL_LLVM_CMPLOG=1でビルドしたバイナリ
+    cmplog: Option<PathBuf>,
+    input_file: Option<InputFileKind>,
+    delivery: Option<Delivery>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TimeoutValue {
+    Msec(u64),
+    Name(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DirsSection {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    dictionaries: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LauncherSection {
+    //Cores::from_cmdlineの形式("0-3", "0,2,4", "all")
+    cores: Option<String>,
+    broker_port: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SyncSection {
+    //afl-fuzz -o sync -S nameで起動したAFL++の出力ディレクトリ(sync/name)
+    peers: Vec<PathBuf>,
+    //秒
+    interval: Option<u64>,
+    export: Option<PathBuf>,
+}
+
+//コマンドラインとキャンペーンファイルを突き合わせた、実際に使う設定
+#[derive(Debug)]
+pub struct Config {
+    pub target: TargetConfig,
+    pub dirs: DirsConfig,
+    pub fuzzer: FuzzerConfig,
+    pub feedback: FeedbackConfig,
+    pub objective: ObjectiveConfig,
+    //Noneならシングルプロセスで実行する
+    pub launcher: Option<LauncherConfig>,
+    pub sync: SyncConfig,
+}
+
+//サブコマンド(tminなど)で使う設定で、ターゲットの実行に必要な部分だけを持つ
+#[derive(Debug)]
+pub struct ToolConfig {
+    pub target: TargetConfig,
+    pub objective: ObjectiveConfig,
+}
+
+#[derive(Debug)]
+pub struct TargetConfig {
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    pub env: BTreeMap<String, String>,
+    pub timeout: Timeout,
+    //入力1KBごとに足す時間で、0なら入力の長さによらない
+    pub timeout_per_kb: Duration,
+    //Noneなら実行し直さずにハングとして扱う
+    pub hang_timeout: Option<Duration>,
+    pub memory_limit: u64,
+    //Noneなら、ターゲットがforkserverのハンドシェイクで報告した大きさにする
+    //指定したときは、報告された大きさの上限になる
+    pub map_size: Option<usize>,
+    //argsとenvはtargetと同じものを使う
+    pub cmplog: Option<OsString>,
+    //@@で渡す入力ファイルをどこに置くか
+    pub input_file: InputFileKind,
+    pub delivery: Delivery,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFileKind {
+    //cur_inputのパスに、実行のたびに書き込む
+    Disk,
+    //memfd_createで作ったメモリ上のファイルを、/proc/<pid>/fd/Nのパスで渡す
+    //ファイルシステムに書き込まず、同じホストの他のfuzzerとパスが重ならない
+    Memfd,
+}
+
+//ターゲットへの入力の渡し方で、省略するとargsに@@があるかで決める
+//ターゲットが__AFL_FUZZ_TESTCASE_BUFを使うときは、どちらでも共有メモリで渡す
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Delivery {
+    //@@をファイルのパスに置き換える
+    File,
+    //標準入力につないだファイルに書き込む
+    Stdin,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Timeout {
+    Fixed(Duration),
+    //afl-fuzzの-tを省略したときと同じく、シードを読み込んだ後に実行時間から求める
+    Auto { multiplier: f64, max: Duration },
+}
+
+impl Timeout {
+    //autoはシードを読み込むまで分からないので、上限で始める
+    //シードを読み込まないサブコマンドでは、ずっとこの値を使う
+    pub fn initial(&self) -> Duration {
+        match self {
+            Self::Fixed(timeout) => *timeout,
+            Self::Auto { max, .. } => *max,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DirsConfig {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub dictionaries: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct LauncherConfig {
+    pub cores: Cores,
+    pub broker_port: u16,
+}
+
+//AFL++のインスタンスとテストケースをやり取りする設定
+//peersが空でexportもNoneなら、何もしない
+#[derive(Debug)]
+pub struct SyncConfig {
+    //それぞれのqueue/から新しいファイルを取り込む
+    pub peers: Vec<PathBuf>,
+    pub interval: Duration,
+    //自分のqueueをAFL++が読める形で書き出すディレクトリ(sync/libafl)
+    pub export: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FuzzerConfig {
+    pub scheduler: SchedulerKind,
+    //schedulerがweightedのときだけ使う
+    pub schedule: PowerScheduleKind,
+    pub mutators: Vec<MutatorKind>,
+    //mutationを選ぶ確率を、MOptで見つけたものの多さに合わせて変えていく
+    pub mopt: bool,
+}
+
+impl Default for FuzzerConfig {
+    fn default() -> Self {
+        Self {
+            scheduler: SchedulerKind::Queue,
+            schedule: PowerScheduleKind::Fast,
+            mutators: vec![
+                MutatorKind::Havoc,
+                MutatorKind::Crossover,
+                MutatorKind::Tokens,
+            ],
+            mopt: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SchedulerKind {
+    //コーパスを先頭から順に選ぶ
+    Queue,
+    //コーパスからランダムに選ぶ
+    Random,
+    //power scheduleで求めた重みで選び、選んだテストケースのmutationの回数も重みで決める
+    Weighted,
+}
+
+//AFLFastのpower schedule(afl-fuzzの-pと同じ)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerScheduleKind {
+    Explore,
+    Fast,
+    Coe,
+    Lin,
+    Quad,
+    Exploit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MutatorKind {
+    //havoc_mutationsのうち、他のテストケースを使わないもの
+    Havoc,
+    //他のテストケースと混ぜ合わせるもの
+    Crossover,
+    //辞書のトークンを挿入・置換するもの
+    Tokens,
+}
+
+//MaxMapFeedback::trackingに渡すフラグ
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedbackConfig {
+    pub track_indexes: bool,
+    pub track_novelties: bool,
+    //新しいコーパスを何度か実行して、実行ごとに変わるエッジを新しいカバレッジとして扱わないようにする
+    pub mask_unstable: bool,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self {
+            track_indexes: true,
+            track_novelties: false,
+            mask_unstable: false,
+        }
+    }
+}
+
+//どの実行結果をBugとして扱うか
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ObjectiveConfig {
+    pub crash: bool,
+    pub timeout: bool,
+    //同じBugを何度も保存しないための基準
+    pub unique: UniquenessPolicy,
+    //stack-hashで、スタックトレースの上から何フレームを比較するか
+    pub stack_frames: usize,
+    //新しいsolutionを何回実行し直して再現するかを調べるか、0なら調べない
+    pub reproduce_runs: usize,
+    //一度も再現しなかったsolutionを捨てる
+    pub discard_unreproducible: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UniquenessPolicy {
+    //クラッシュ・ハングをすべて保存する
+    All,
+    //新しいカバレッジを伴う場合のみ保存する
+    NewCoverage,
+    //カバレッジマップ全体のハッシュが新しい場合のみ保存する
+    CoverageHash,
+    //ASanが出力したスタックのハッシュが新しい場合のみ保存する
+    StackHash,
+}
+
+impl Default for ObjectiveConfig {
+    fn default() -> Self {
+        Self {
+            crash: true,
+            timeout: true,
+            unique: UniquenessPolicy::NewCoverage,
+            stack_frames: 5,
+            reproduce_runs: 0,
+            discard_unreproducible: false,
+        }
+    }
+}
+
+impl Config {
+    //キャンペーンファイルがあれば読み込み、コマンドラインの指定で上書きする
+    pub fn load(options: &Options) -> Result<Self, Error> {
+        let campaign = match &options.target.campaign {
+            Some(path) => read_campaign(path)?,
+            None => Campaign::default(),
+        };
+
+        let mut target = TargetConfig::resolve(&options.target, campaign.target)?;
+        if let Some(cmplog) = &options.cmplog {
+            target.cmplog = Some(cmplog.clone().into_os_string());
+        }
+
+        let input = options
+            .input
+            .clone()
+            .or(campaign.dirs.input)
+            .ok_or_else(|| {
+                invalid(
+                    "dirs.input",
+                    "is not set; pass -i or set it in the campaign file",
+                )
+            })?;
+        if !input.is_dir() {
+            return Err(invalid(
+                "dirs.input",
+                format!("{} is not a directory", input.display()),
+            ));
+        }
+
+        let output = options
+            .output
+            .clone()
+            .or(campaign.dirs.output)
+            .ok_or_else(|| {
+                invalid(
+                    "dirs.output",
+                    "is not set; pass -o or set it in the campaign file",
+                )
+            })?;
+
+        //-xはキャンペーンファイルの辞書に追加する
+        let mut dictionaries = campaign.dirs.dictionaries;
+        dictionaries.extend(options.dictionaries.iter().cloned());
+        if let Some(path) = dictionaries.iter().find(|path| !path.exists()) {
+            return Err(invalid(
+                "dirs.dictionaries",
+                format!("{} does not exist", path.display()),
+            ));
+        }
+
+        let dirs = DirsConfig {
+            input,
+            output,
+            dictionaries,
+        };
+
+        if campaign.fuzzer.mutators.is_empty() {
+            return Err(invalid(
+                "fuzzer.mutators",
+                "must contain at least one mutator set",
+            ));
+        }
+
+        //MOptは全てのmutationの確率を自分で決めるので、一部を外すことはできない
+        let all_mutators = [
+            MutatorKind::Havoc,
+            MutatorKind::Crossover,
+            MutatorKind::Tokens,
+        ];
+        if campaign.fuzzer.mopt
+            && !all_mutators
+                .iter()
+                .all(|kind| campaign.fuzzer.mutators.contains(kind))
+        {
+            return Err(invalid(
+                "fuzzer.mutators",
+                "must contain every mutator set when `mopt` is enabled",
+            ));
+        }
+
+        if !campaign.objective.crash && !campaign.objective.timeout {
+            return Err(invalid(
+                "objective",
+                "at least one of `crash` or `timeout` must be enabled",
+            ));
+        }
+
+        //コアの指定があるときだけ、Launcherで複数のクライアントを起動する
+        let launcher = match options.cores.as_ref().or(campaign.launcher.cores.as_ref()) {
+            Some(cores) => Some(LauncherConfig {
+                cores: Cores::from_cmdline(cores)
+                    .map_err(|err| invalid("launcher.cores", format!("{cores:?}: {err}")))?,
+                broker_port: options
+                    .broker_port
+                    .or(campaign.launcher.broker_port)
+                    .unwrap_or(DEFAULT_BROKER_PORT),
+            }),
+            None => None,
+        };
+
+        validate_objective(&campaign.objective)?;
+        let sync = SyncConfig::resolve(campaign.sync)?;
+
+        Ok(Self {
+            target,
+            dirs,
+            fuzzer: campaign.fuzzer,
+            feedback: campaign.feedback,
+            objective: campaign.objective,
+            launcher,
+            sync,
+        })
+    }
+}
+
+impl ToolConfig {
+    //キャンペーンファイルのうち、[target]と[objective]だけを使う
+    pub fn load(options: &TargetOptions) -> Result<Self, Error> {
+        let campaign = match &options.campaign {
+            Some(path) => read_campaign(path)?,
+            None => Campaign::default(),
+        };
+
+        let target = TargetConfig::resolve(options, campaign.target)?;
+        validate_objective(&campaign.objective)?;
+
+        Ok(Self {
+            target,
+            objective: campaign.objective,
+        })
+    }
+}
+
+impl TargetConfig {
+    fn resolve(options: &TargetOptions, section: TargetSection) -> Result<Self, Error> {
+        let (program, args) = match options.command.split_first() {
+            Some((program, args)) => (program.clone(), args.to_vec()),
+            None => {
+                let program = section.program.ok_or_else(|| {
+                    invalid(
+                        "target.program",
+                        "is not set; give the target after `--` or in the campaign file",
+                    )
+                })?;
+                let args = section.args.into_iter().map(OsString::from);
+                (program.into_os_string(), args.collect())
+            }
+        };
+
+        //Noneならautoで、コマンドラインの-tを優先する
+        let fixed = match (options.timeout, section.timeout) {
+            (Some(TimeoutArg::Fixed(timeout)), _) => Some(timeout),
+            (Some(TimeoutArg::Auto), _) => None,
+            (None, Some(TimeoutValue::Msec(0))) => {
+                return Err(invalid("target.timeout", "must be greater than 0"));
+            }
+            (None, Some(TimeoutValue::Msec(msec))) => Some(Duration::from_millis(msec)),
+            (None, Some(TimeoutValue::Name(name))) if name == "auto" => None,
+            (None, Some(TimeoutValue::Name(name))) => {
+                return Err(invalid(
+                    "target.timeout",
+                    format!("{name:?} is neither milliseconds nor \"auto\""),
+                ));
+            }
+            (None, None) => Some(Duration::from_millis(DEFAULT_TIMEOUT_MS)),
+        };
+
+        let timeout = match fixed {
+            Some(timeout) => Timeout::Fixed(timeout),
+            None => {
+                let multiplier = section
+                    .auto_timeout_multiplier
+                    .unwrap_or(DEFAULT_AUTO_TIMEOUT_MULTIPLIER);
+                if multiplier.is_nan() || multiplier < 1.0 {
+                    return Err(invalid(
+                        "target.auto_timeout_multiplier",
+                        "must be at least 1",
+                    ));
+                }
+
+                let max = section
+                    .auto_timeout_max
+                    .unwrap_or(DEFAULT_AUTO_TIMEOUT_MAX_MS);
+                if max == 0 {
+                    return Err(invalid("target.auto_timeout_max", "must be greater than 0"));
+                }
+
+                Timeout::Auto {
+                    multiplier,
+                    max: Duration::from_millis(max),
+                }
+            }
+        };
+
+        let hang_timeout = match (options.hang_timeout, section.hang_timeout) {
+            (Some(timeout), _) => Some(timeout),
+            (None, Some(0)) => {
+                return Err(invalid("target.hang_timeout", "must be greater than 0"));
+            }
+            (None, msec) => msec.map(Duration::from_millis),
+        };
+
+        let map_size = options.map_size.or(section.map_size);
+        if map_size == Some(0) {
+            return Err(invalid("target.map_size", "must be greater than 0"));
+        }
+
+        let has_input_arg = args.iter().any(|arg| arg == "@@");
+        let delivery = match section.delivery {
+            Some(Delivery::File) if !has_input_arg => {
+                return Err(invalid(
+                    "target.delivery",
+                    "\"file\" needs @@ in the target arguments",
+                ));
+            }
+            Some(Delivery::Stdin) if has_input_arg => {
+                return Err(invalid(
+                    "target.delivery",
+                    "\"stdin\" cannot be used with @@ in the target arguments",
+                ));
+            }
+            Some(delivery) => delivery,
+            None if has_input_arg => Delivery::File,
+            None => Delivery::Stdin,
+        };
+
+        Ok(Self {
+            program,
+            args,
+            env: section.env,
+            timeout,
+            timeout_per_kb: Duration::from_millis(section.timeout_per_kb.unwrap_or(0)),
+            hang_timeout,
+            memory_limit: options.memory_limit.or(section.memory_limit).unwrap_or(0),
+            map_size,
+            cmplog: section.cmplog.map(PathBuf::into_os_string),
+            input_file: section.input_file.unwrap_or(InputFileKind::Disk),
+            delivery,
+        })
+    }
+}
+
+impl SyncConfig {
+    fn resolve(section: SyncSection) -> Result<Self, Error> {
+        let interval = section.interval.unwrap_or(DEFAULT_SYNC_INTERVAL_SECS);
+        if interval == 0 {
+            return Err(invalid("sync.interval", "must be greater than 0"));
+        }
+
+        //自分で書き出したものを読み直さないようにする
+        if let Some(export) = &section.export {
+            if section.peers.contains(export) {
+                return Err(invalid(
+                    "sync.peers",
+                    format!("{} is also the export directory", export.display()),
+                ));
+            }
+        }
+
+        Ok(Self {
+            peers: section.peers,
+            interval: Duration::from_secs(interval),
+            export: section.export,
+        })
+    }
+}
+
+fn validate_objective(objective: &ObjectiveConfig) -> Result<(), Error> {
+    if objective.stack_frames == 0 {
+        return Err(invalid("objective.stack_frames", "must be greater than 0"));
+    }
+    if objective.discard_unreproducible && objective.reproduce_runs == 0 {
+        return Err(invalid(
+            "objective.discard_unreproducible",
+            "requires `reproduce_runs` to be greater than 0",
+        ));
+    }
+    Ok(())
+}
+
+fn read_campaign(path: &Path) -> Result<Campaign, Error> {
+    let text = fs::read_to_string(path).map_err(|err| {
+        Error::illegal_argument(format!(
+            "failed to read campaign file {}: {err}",
+            path.display()
+        ))
+    })?;
+
+    //tomlのエラーには、問題のあるキーと行・桁が含まれる
+    toml::from_str(&text).map_err(|err| {
+        Error::illegal_argument(format!("invalid campaign file {}: {err}", path.display()))
+    })
+}
+
+fn invalid(key: &str, msg: impl Display) -> Error {
+    Error::illegal_argument(format!("invalid `{key}`: {msg}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    //コマンドラインの引数と[target]のTOMLから、TargetConfigを作る
+    fn resolve(args: &[&str], section: &str) -> Result<TargetConfig, Error> {
+        let mut argv = vec!["libafl-sample", "-i", "in", "-o", "out"];
+        argv.extend_from_slice(args);
+        let options = Options::parse_from(argv);
+        TargetConfig::resolve(&options.target, toml::from_str(section).unwrap())
+    }
+
+    fn assert_invalid(result: Result<TargetConfig, Error>, key: &str) {
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains(&format!("invalid `{key}`")), "{err}");
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let config = resolve(
+            &["-t", "200", "--", "./cli", "@@"],
+            r#"
+                program = "./campaign"
+                timeout = "auto"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.program, "./cli");
+        assert!(
+            matches!(config.timeout, Timeout::Fixed(timeout) if timeout == Duration::from_millis(200))
+        );
+
+        let config = resolve(&["-t", "auto", "--", "./cli"], "timeout = 100").unwrap();
+        assert!(matches!(
+            config.timeout,
+            Timeout::Auto { max, .. } if max == Duration::from_millis(DEFAULT_AUTO_TIMEOUT_MAX_MS)
+        ));
+    }
+
+    #[test]
+    fn defaults_follow_the_command() {
+        let config = resolve(&["--", "./target", "@@"], "").unwrap();
+        assert!(
+            matches!(config.timeout, Timeout::Fixed(timeout) if timeout == Duration::from_millis(DEFAULT_TIMEOUT_MS))
+        );
+        assert_eq!(config.delivery, Delivery::File);
+        assert_eq!(config.input_file, InputFileKind::Disk);
+        assert_eq!(config.memory_limit, 0);
+
+        let config = resolve(&["--campaign", "campaign.toml"], "program = \"./target\"").unwrap();
+        assert_eq!(config.delivery, Delivery::Stdin);
+    }
+
+    //エラーは、どのキーが不正かを示す
+    #[test]
+    fn errors_name_the_key() {
+        assert_invalid(
+            resolve(&["--campaign", "campaign.toml"], ""),
+            "target.program",
+        );
+        assert_invalid(resolve(&["--", "./t"], "timeout = 0"), "target.timeout");
+        assert_invalid(
+            resolve(&["--", "./t"], "timeout = \"fast\""),
+            "target.timeout",
+        );
+        assert_invalid(
+            resolve(
+                &["--", "./t"],
+                "timeout = \"auto\"\nauto_timeout_multiplier = 0.5",
+            ),
+            "target.auto_timeout_multiplier",
+        );
+        assert_invalid(
+            resolve(&["--", "./t"], "timeout = \"auto\"\nauto_timeout_max = 0"),
+            "target.auto_timeout_max",
+        );
+        assert_invalid(
+            resolve(&["--", "./t"], "hang_timeout = 0"),
+            "target.hang_timeout",
+        );
+        assert_invalid(resolve(&["--", "./t"], "map_size = 0"), "target.map_size");
+        assert_invalid(
+            resolve(&["--", "./t"], "delivery = \"file\""),
+            "target.delivery",
+        );
+        assert_invalid(
+            resolve(&["--", "./t", "@@"], "delivery = \"stdin\""),
+            "target.delivery",
+        );
+    }
+}
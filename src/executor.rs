@@ -0,0 +1,524 @@
+use std::{
+    ffi::OsStr,
+    fmt::Debug,
+    fs,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use libafl::{
+    executors::{
+        forkserver::{ForkserverExecutorBuilder, HasForkserver},
+        ExitKind, HasObservers,
+    },
+    inputs::{BytesInput, HasTargetBytes, UsesInput},
+    observers::{get_asan_runtime_flags, Observer, ObserversTuple, UsesObservers},
+    prelude::{ForkserverExecutor, HitcountsMapObserver, StdMapObserver, TimeObserver},
+    state::UsesState,
+    Error,
+};
+
+use libafl_bolts::{
+    fs::get_unique_std_input_file,
+    shmem::{ShMem, ShMemProvider, UnixShMemProvider},
+    tuples::{tuple_list, tuple_list_type, MatchName},
+    AsMutSlice, AsSlice, Named,
+};
+use nix::{
+    sys::{
+        memfd::{memfd_create, MemFdCreateFlag},
+        signal::{kill, Signal},
+        time::{TimeSpec, TimeValLike},
+    },
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Delivery, InputFileKind, TargetConfig},
+    runner::RunnerState,
+    stacktrace::{StackHashObserver, STACK_NAME},
+};
+
+pub type CoverageObserver<'a> = HitcountsMapObserver<StdMapObserver<'a, u8, false>>;
+pub type Observers<'a> = tuple_list_type!(
+    CoverageObserver<'a>,
+    TimeObserver,
+    StackHashObserver,
+    SignalObserver,
+    SlowObserver
+);
+pub type Executor<'a, S> = TargetExecutor<ForkserverExecutor<Observers<'a>, S, UnixShMemProvider>>;
+//objectiveが再現性を調べるときに使うexecutorで、終わり方しか見ないのでobserverを持たない
+pub type ReproduceExecutor<S> = TargetExecutor<ForkserverExecutor<(), S, UnixShMemProvider>>;
+
+//SignalObserverとSlowObserverの名前で、TargetExecutorはこの名前でobserverを探す
+pub const SIGNAL_NAME: &str = "signal";
+pub const SLOW_NAME: &str = "slow";
+
+//__AFL_SHM_FUZZ_IDの共有メモリの先頭にある、入力の長さ
+const SHMEM_FUZZ_HDR_SIZE: usize = 4;
+//ハンドシェイクでマップの大きさを報告しないターゲット(afl-gcc-fastなど)で使う大きさ
+const DEFAULT_MAP_SIZE: usize = 65536;
+//afl-fuzzのDEFAULT_SHMEM_SIZEと同じく、大きさを調べるときはLTOのターゲットでも足りる大きさで起動する
+const PROBE_MAP_SIZE: usize = 1 << 23;
+
+//afl-fuzzと同じく、forkserverを1度起動して、ハンドシェイクで報告されたカバレッジマップの大きさを調べる
+//大きさを指定していれば、それを超えるターゲットはエッジが衝突するのでエラーにする
+pub fn map_size(target: &TargetConfig, program: &OsStr, cur_input: &Path) -> Result<usize, Error> {
+    let shmem = UnixShMemProvider::new()?.new_shmem(PROBE_MAP_SIZE)?;
+    shmem.write_to_env("__AFL_SHM_ID")?;
+    let (input_path, _memfd) = input_path(target, cur_input)?;
+
+    //ターゲットはAFL_MAP_SIZEを共有メモリの大きさとして扱う
+    //forkserverはexecutorを捨てるとパイプが閉じられて終了する
+    let reported = builder(target, program, &input_path, None)
+        .env("AFL_MAP_SIZE", PROBE_MAP_SIZE.to_string())
+        .build::<_, RunnerState>(tuple_list!())?
+        .coverage_map_size();
+
+    match (reported, target.map_size) {
+        (Some(reported), Some(size)) if reported > size => Err(Error::illegal_argument(format!(
+            "{} needs a coverage map of {reported} bytes, but only {size} bytes are allocated; \
+             set --map-size, AFL_MAP_SIZE or `target.map_size` to at least {reported}",
+            program.to_string_lossy()
+        ))),
+        (Some(reported), _) => Ok(reported),
+        (None, size) => Ok(size.unwrap_or(DEFAULT_MAP_SIZE)),
+    }
+}
+
+//@@はcur_input(target.input_fileがmemfdならmemfdのパス)に置き換えて、ターゲットにファイルで入力を渡す
+//target.deliveryがstdinなら(@@がなければ)、標準入力で渡す
+//複数のクライアントが同じファイルに書き込まないように、cur_inputはクライアントごとに分ける
+//programは通常target.programだが、CmpLog用のバイナリも同じ設定で起動する
+//map_sizeは__AFL_SHM_IDの共有メモリの大きさで、map_sizeで調べたもの
+pub fn build<OT, S>(
+    target: &TargetConfig,
+    program: &OsStr,
+    cur_input: &Path,
+    asan_log: Option<&Path>,
+    map_size: usize,
+    observers: OT,
+) -> Result<TargetExecutor<ForkserverExecutor<OT, S, UnixShMemProvider>>, Error>
+where
+    OT: ObserversTuple<S>,
+    S: UsesInput<Input = BytesInput>,
+{
+    //__AFL_FUZZ_TESTCASE_BUFを使うハーネスには、__AFL_SHM_FUZZ_IDの共有メモリで入力を渡す
+    //ターゲットがハンドシェイクで対応していると伝えなければ、これまで通りファイルか標準入力で渡す
+    let mut testcase_shmem = UnixShMemProvider::new()?;
+    let (input_path, memfd) = input_path(target, cur_input)?;
+    let executor = builder(target, program, &input_path, asan_log)
+        .env("AFL_MAP_SIZE", map_size.to_string())
+        .coverage_map_size(map_size)
+        .shmem_provider(&mut testcase_shmem)
+        .build(observers)?;
+
+    //標準入力では、libaflがカレントディレクトリに作った.cur_input_<pid>をターゲットの標準入力にdup2している
+    //パイプと違い、ターゲットが読み残しても書き込みは詰まらず、大きな入力もそのまま渡せる
+    //write_inputで書くたびに長さを切り詰めるので、ターゲットは入力の終わりでEOFを読む
+    //ターゲットは開いたままなので名前はすぐ消し、CmpLogのexecutorが同じ名前で別のファイルを作れるようにする
+    if target.delivery == Delivery::Stdin {
+        fs::remove_file(get_unique_std_input_file())?;
+    }
+
+    Ok(TargetExecutor::new(
+        executor,
+        target.timeout.initial(),
+        target.timeout_per_kb,
+        target.hang_timeout,
+        memfd,
+    ))
+}
+
+//@@で渡すファイルのパスで、memfdのときはexecutorが使い終わるまでmemfdを持っておく
+fn input_path(target: &TargetConfig, cur_input: &Path) -> Result<(PathBuf, Option<Memfd>), Error> {
+    if target.delivery == Delivery::File && target.input_file == InputFileKind::Memfd {
+        let memfd = Memfd::new()?;
+        Ok((memfd.path(), Some(memfd)))
+    } else {
+        Ok((cur_input.to_path_buf(), None))
+    }
+}
+
+//memfd_createで作った、ファイルシステムにないファイル
+//MFD_CLOEXECなのでターゲットには引き継がれず、ターゲットはfuzzerの/proc/<pid>/fd/Nを開き直す
+//パスにpidが入るので、同じホストのfuzzerどうしで入力ファイルが重ならない
+#[derive(Debug)]
+struct Memfd(OwnedFd);
+
+impl Memfd {
+    fn new() -> Result<Self, Error> {
+        let fd = memfd_create(c"libafl-cur-input", MemFdCreateFlag::MFD_CLOEXEC).map_err(|err| {
+            Error::unknown(format!(
+                "memfd_create failed: {err}; set `target.input_file = \"disk\"` to write the input to a file"
+            ))
+        })?;
+        //memfd_createが返したfdは、ほかに持ち主がいない
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    fn path(&self) -> PathBuf {
+        PathBuf::from(format!("/proc/{}/fd/{}", process::id(), self.0.as_raw_fd()))
+    }
+}
+
+fn builder(
+    target: &TargetConfig,
+    program: &OsStr,
+    cur_input: &Path,
+    asan_log: Option<&Path>,
+) -> ForkserverExecutorBuilder<'static, UnixShMemProvider> {
+    //forkserverは典型的なfork -> executeではない
+    //プログラムの開始部分で停止し、指示待ちする。支持ありの場合は、forkする
+    //そのため、executeのコストを削減できる
+    //ForkserverExecutorの場合ははじめのプロセスは、build時に生成される
+    //Exexutor::run_targetでは、はじめのプロセスにforkの指示を送るだけ
+    let mut builder = ForkserverExecutor::builder();
+
+    //afl-fuzzの-mと同様に、ターゲットの仮想メモリ(RLIMIT_AS)を制限する
+    //builderにはメモリ制限を渡す口がないので、shのulimitを経由してexecする
+    if target.memory_limit > 0 {
+        builder = builder
+            .program("/bin/sh")
+            .arg("-c")
+            .arg(format!(
+                "ulimit -v {}; exec \"$@\"",
+                target.memory_limit << 10
+            ))
+            .arg("sh")
+            .arg(program);
+    } else {
+        builder = builder.program(program);
+    }
+
+    for arg in &target.args {
+        builder = if arg == "@@" {
+            builder.arg_input_file(cur_input)
+        } else {
+            builder.arg(arg)
+        };
+    }
+
+    builder = builder.envs(&target.env);
+
+    //ASanのレポートはStackHashObserverが読むので、クライアントごとのディレクトリに書かせる
+    //ASAN_OPTIONSの指定がなければ、libaflの推奨する設定を使う
+    if let Some(asan_log) = asan_log {
+        let asan_options = format!(
+            "{}:log_path={}",
+            target
+                .env
+                .get("ASAN_OPTIONS")
+                .cloned()
+                .unwrap_or_else(get_asan_runtime_flags),
+            asan_log.display()
+        );
+        builder = builder.env("ASAN_OPTIONS", asan_options);
+    }
+
+    builder
+}
+
+//ターゲットが終了したシグナルの番号
+//ExitKind::Crashにはシグナルが含まれないので、TargetExecutorが書き込む
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignalObserver {
+    name: String,
+    signal: Option<i32>,
+}
+
+impl SignalObserver {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            signal: None,
+        }
+    }
+
+    pub fn signal(&self) -> Option<i32> {
+        self.signal
+    }
+}
+
+impl Named for SignalObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Observer<S> for SignalObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.signal = None;
+        Ok(())
+    }
+}
+
+//libaflのTimeoutForkserverExecutorとほぼ同じだが、forkserverから受け取った終了ステータスを
+//SignalObserverに残す
+//TimeoutForkserverExecutorは内側のForkserverExecutorを外に見せないので、ステータスを読めない
+#[derive(Debug)]
+pub struct TargetExecutor<E> {
+    executor: E,
+    timeout: Duration,
+    //入力1KBごとにtimeoutに足す時間
+    timeout_per_kb: Duration,
+    //タイムアウトした入力を、この時間で1回だけ実行し直す
+    hang_timeout: Option<Duration>,
+    //@@のパスが指すmemfdで、閉じるとターゲットが入力を開けなくなる
+    _memfd: Option<Memfd>,
+}
+
+impl<E> TargetExecutor<E> {
+    fn new(
+        executor: E,
+        timeout: Duration,
+        timeout_per_kb: Duration,
+        hang_timeout: Option<Duration>,
+        memfd: Option<Memfd>,
+    ) -> Self {
+        Self {
+            executor,
+            timeout,
+            timeout_per_kb,
+            hang_timeout,
+            _memfd: memfd,
+        }
+    }
+
+    //autoのタイムアウトは、シードを読み込んだ後で決まる
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+impl<E, EM, Z> libafl::executors::Executor<EM, Z> for TargetExecutor<E>
+where
+    E: libafl::executors::Executor<EM, Z> + HasForkserver + HasObservers + Debug,
+    E::Input: HasTargetBytes,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    fn run_target(
+        &mut self,
+        _fuzzer: &mut Z,
+        state: &mut Self::State,
+        _mgr: &mut EM,
+        input: &Self::Input,
+    ) -> Result<ExitKind, Error> {
+        self.run(state, input)
+    }
+}
+
+impl<E> TargetExecutor<E>
+where
+    E: HasForkserver + HasObservers,
+    E::Input: HasTargetBytes,
+{
+    //fuzzerとevent managerを使わないので、objectiveのfeedbackからも実行できる
+    pub fn run(&mut self, state: &mut E::State, input: &E::Input) -> Result<ExitKind, Error> {
+        let bytes = input.target_bytes();
+        self.write_input(bytes.as_slice())?;
+
+        //長い入力ほど、ターゲットが読み込んで処理するのに時間がかかる
+        let extra = self
+            .timeout_per_kb
+            .mul_f64(bytes.as_slice().len() as f64 / 1024.0);
+
+        let timeout = self.timeout + extra;
+        let exit_kind = self.run_forkserver(timeout)?;
+
+        //afl-fuzzのAFL_HANG_TMOUTと同じく、長いタイムアウトで実行し直して、本当にハングしているか確かめる
+        //observerは1回目の結果を持っているので、初めから実行し直したのと同じになるように戻す
+        let hang_timeout = self.hang_timeout.map(|timeout| timeout + extra);
+        let exit_kind = match hang_timeout {
+            Some(hang_timeout) if exit_kind == ExitKind::Timeout && hang_timeout > timeout => {
+                self.executor.observers_mut().pre_exec_all(state, input)?;
+                let exit_kind = self.run_forkserver(hang_timeout)?;
+
+                //終わったものは、遅いだけの入力として印を付ける
+                if exit_kind != ExitKind::Timeout {
+                    if let Some(observer) = self
+                        .executor
+                        .observers_mut()
+                        .match_name_mut::<SlowObserver>(SLOW_NAME)
+                    {
+                        observer.timeout = Some(timeout);
+                    }
+                }
+                exit_kind
+            }
+            _ => exit_kind,
+        };
+
+        Ok(exit_kind)
+    }
+}
+
+impl<E> TargetExecutor<E>
+where
+    E: HasForkserver + HasObservers,
+{
+    pub fn uses_shmem_testcase(&self) -> bool {
+        self.executor.uses_shmem_testcase()
+    }
+
+    //共有メモリでは、先頭4バイトに長さを書き、続けて入力を書く
+    //入りきらない入力は、afl-fuzzと同じく切り詰める
+    fn write_input(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if !self.executor.uses_shmem_testcase() {
+            return self.executor.input_file_mut().write_buf(bytes);
+        }
+
+        let shmem = self
+            .executor
+            .shmem_mut()
+            .as_mut()
+            .ok_or_else(|| Error::illegal_state("testcase shared memory is not mapped"))?;
+        let slice = shmem.as_mut_slice();
+        let len = bytes.len().min(slice.len() - SHMEM_FUZZ_HDR_SIZE);
+        #[allow(clippy::cast_possible_truncation)]
+        slice[..SHMEM_FUZZ_HDR_SIZE].copy_from_slice(&(len as u32).to_ne_bytes());
+        slice[SHMEM_FUZZ_HDR_SIZE..SHMEM_FUZZ_HDR_SIZE + len].copy_from_slice(&bytes[..len]);
+        Ok(())
+    }
+
+    //入力は書き込んであるものとして、forkserverに1回実行させる
+    fn run_forkserver(&mut self, timeout: Duration) -> Result<ExitKind, Error> {
+        let timeout = TimeSpec::milliseconds(timeout.as_millis() as i64);
+
+        //標準入力はターゲットとファイルのオフセットを共有しているので、前の実行が読んだ分を戻す
+        //hang_timeoutで実行し直すときは、入力を書き直さない
+        self.executor.input_file_mut().rewind()?;
+
+        //前回タイムアウトしていれば、forkserverに子プロセスをkillしたことを伝える
+        let last_run_timed_out = self.executor.forkserver().last_run_timed_out();
+        let forkserver = self.executor.forkserver_mut();
+        if forkserver.write_ctl(last_run_timed_out)? != 4 {
+            return Err(Error::unknown(
+                "Unable to request new process from fork server (OOM?)",
+            ));
+        }
+        forkserver.set_last_run_timed_out(0);
+
+        let (recv_pid_len, pid) = forkserver.read_st()?;
+        if recv_pid_len != 4 || pid <= 0 {
+            return Err(Error::unknown("Fork server is misbehaving (OOM?)"));
+        }
+        forkserver.set_child_pid(Pid::from_raw(pid));
+
+        let exit_kind = match forkserver.read_st_timed(&timeout)? {
+            Some(status) => {
+                forkserver.set_status(status);
+                if libc::WIFSIGNALED(status) {
+                    let signal = libc::WTERMSIG(status);
+                    if let Some(observer) = self
+                        .executor
+                        .observers_mut()
+                        .match_name_mut::<SignalObserver>(SIGNAL_NAME)
+                    {
+                        observer.signal = Some(signal);
+                    }
+                    ExitKind::Crash
+                } else {
+                    ExitKind::Ok
+                }
+            }
+            None => {
+                //killしないと、次のread_stで正しいpidを受け取れない
+                forkserver.set_last_run_timed_out(1);
+                let _ = kill(forkserver.child_pid(), Signal::SIGKILL);
+                let (recv_status_len, _) = forkserver.read_st()?;
+                if recv_status_len != 4 {
+                    return Err(Error::unknown("Could not kill timed-out child"));
+                }
+                ExitKind::Timeout
+            }
+        };
+
+        self.executor
+            .forkserver_mut()
+            .set_child_pid(Pid::from_raw(0));
+
+        //ASanのレポートは<log_path>.<pid>に書かれるので、どの子プロセスのものを読むかを伝える
+        if let Some(observer) = self
+            .executor
+            .observers_mut()
+            .match_name_mut::<StackHashObserver>(STACK_NAME)
+        {
+            observer.set_pid(pid);
+        }
+
+        Ok(exit_kind)
+    }
+}
+
+impl<E> UsesState for TargetExecutor<E>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E> UsesObservers for TargetExecutor<E>
+where
+    E: UsesObservers,
+{
+    type Observers = E::Observers;
+}
+
+impl<E> HasObservers for TargetExecutor<E>
+where
+    E: HasObservers,
+{
+    fn observers(&self) -> &Self::Observers {
+        self.executor.observers()
+    }
+
+    fn observers_mut(&mut self) -> &mut Self::Observers {
+        self.executor.observers_mut()
+    }
+}
+
+//タイムアウトしたが、長いタイムアウトで実行し直すと終わったときの、1回目のタイムアウト
+//TargetExecutorが書き込み、SlowFeedbackがテストケースのメタデータにする
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SlowObserver {
+    name: String,
+    timeout: Option<Duration>,
+}
+
+impl SlowObserver {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            timeout: None,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+impl Named for SlowObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Observer<S> for SlowObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.timeout = None;
+        Ok(())
+    }
+}
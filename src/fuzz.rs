@@ -0,0 +1,915 @@
+use std::{
+    fs,
+    marker::PhantomData,
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    events::{Event, EventFirer},
+    feedback_and_fast, feedback_or, feedback_or_fast,
+    inputs::Input,
+    monitors::UserStats,
+    prelude::{
+        havoc_mutations, tokens_mutations, AFLppCmpMap, AFLppCmpObserver, AFLppRedQueen,
+        BytesInput, ConstFeedback, Corpus, CrashFeedback, EagerOrFeedback, EventConfig,
+        EventManager, FastAndFeedback, FastOrFeedback, HitcountsMapObserver, I2SRandReplace,
+        InMemoryOnDiskCorpus, Launcher, MaxMapFeedback, MutatorsTuple, SimpleEventManager,
+        SimpleMonitor, StdMapObserver, StdScheduledMutator, TimeFeedback, TimeObserver,
+        TimeoutFeedback, Tokens,
+    },
+    schedulers::IndexesLenTimeMinimizerScheduler,
+    stages::{
+        mutational::MultiMutationalStage, tracing::AFLppCmplogTracingStage, CalibrationStage,
+        ColorizationStage, StdMutationalStage,
+    },
+    state::UsesState,
+    state::{HasCorpus, HasMetadata, HasStartTime, StdState},
+    Error, Evaluator, Fuzzer, StdFuzzer,
+};
+
+use libafl_bolts::{
+    core_affinity::CoreId,
+    current_nanos, current_time,
+    rands::StdRand,
+    shmem::{ShMem, ShMemProvider, StdShMemProvider},
+    tuples::{tuple_list, Merge},
+    AsMutSlice, Named,
+};
+
+use crate::{
+    afl::{AflCorpus, OriginFeedback, SeedSource},
+    calibration::StabilityStage,
+    cmplog::{CmpLogStage, CmpValuesStage},
+    config::{Config, Delivery, InputFileKind, LauncherConfig, SchedulerKind, Timeout},
+    executor::{
+        self, CoverageObserver, Executor, Observers, ReproduceExecutor, SignalObserver,
+        SlowObserver, SIGNAL_NAME, SLOW_NAME,
+    },
+    hang::SlowFeedback,
+    mutators::{BaseMutator, MOptStatsStage},
+    power::ScheduledMutationalStage,
+    reproduce::ReproduceFeedback,
+    resume::{Snapshot, SnapshotStage},
+    scheduler::BaseScheduler,
+    sidecar::SolutionInfoFeedback,
+    solutions::{
+        ExitOkFeedback, SolutionCorpus, SolutionCounts, SolutionKind, SolutionKindFeedback,
+    },
+    stacktrace::{StackHashObserver, STACK_NAME},
+    stats::AflStatsStage,
+    sync::AflSyncStage,
+    timeout::{self, AutoTimeout},
+    uniqueness::UniquenessFeedback,
+};
+
+pub type FuzzState = StdState<
+    BytesInput,
+    AflCorpus<InMemoryOnDiskCorpus<BytesInput>>,
+    StdRand,
+    SolutionCorpus<BytesInput>,
+>;
+
+//カバレッジのobserverの名前
+const MAP_NAME: &str = "shmem";
+const CMPLOG_NAME: &str = "cmplog";
+//再開するときに、前回のqueueを移しておくディレクトリ(afl-fuzzと同じ名前)
+const RESUME_DIR: &str = "_resume";
+//Launcherのクライアントのインスタンスのディレクトリ名で、後ろにコア番号を付ける
+const INSTANCE_PREFIX: &str = "client";
+
+type CorpusFeedback<'a> = FastAndFeedback<
+    ExitOkFeedback,
+    EagerOrFeedback<
+        MaxMapFeedback<CoverageObserver<'a>, FuzzState, u8>,
+        EagerOrFeedback<
+            TimeFeedback,
+            EagerOrFeedback<SlowFeedback, OriginFeedback, FuzzState>,
+            FuzzState,
+        >,
+        FuzzState,
+    >,
+    FuzzState,
+>;
+
+type ToggledFeedback<F> = FastAndFeedback<ConstFeedback, F, FuzzState>;
+
+type ObjectiveFeedback<'a> = FastAndFeedback<
+    FastOrFeedback<ToggledFeedback<CrashFeedback>, ToggledFeedback<TimeoutFeedback>, FuzzState>,
+    FastAndFeedback<
+        UniquenessFeedback<CoverageObserver<'a>, FuzzState>,
+        FastAndFeedback<
+            ReproduceFeedback<ReproduceExecutor<FuzzState>>,
+            FastAndFeedback<SolutionKindFeedback, SolutionInfoFeedback, FuzzState>,
+            FuzzState,
+        >,
+        FuzzState,
+    >,
+    FuzzState,
+>;
+
+type FuzzerType<'a> = StdFuzzer<
+    IndexesLenTimeMinimizerScheduler<BaseScheduler<CoverageObserver<'a>, FuzzState>>,
+    CorpusFeedback<'a>,
+    ObjectiveFeedback<'a>,
+    Observers<'a>,
+>;
+
+pub fn fuzz(config: &Config) -> Result<(), Error> {
+    fs::create_dir_all(&config.dirs.output)?;
+
+    match &config.launcher {
+        Some(launcher) => launch(config, launcher),
+        None => {
+            //シングルプロセスで実行される
+            let monitor = SimpleMonitor::with_user_monitor(|s| println!("{s}"), true);
+            let manager = SimpleEventManager::new(monitor);
+            run_client(config, None, manager, None)
+        }
+    }
+}
+
+//afl-fuzzのin-place resumeと同じく、前回のqueueを_resumeに移してから始める
+//queueに残したまま読み直すと、同じテストケースが新しいidでもう一度保存される
+//再開しないときも移しておき、queueのファイルのidとsrcがこのキャンペーンのCorpusIdと一致するようにする
+fn prepare_resume(instance_dir: &Path) -> Result<(), Error> {
+    let queue_dir = instance_dir.join("queue");
+    let resume_dir = instance_dir.join(RESUME_DIR);
+
+    let empty = match fs::read_dir(&queue_dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+    if empty {
+        return Ok(());
+    }
+
+    //前回の再開で読み直したものは、すべてqueueに入っている
+    if resume_dir.exists() {
+        fs::remove_dir_all(&resume_dir)?;
+    }
+    fs::rename(&queue_dir, &resume_dir)?;
+    Ok(())
+}
+
+//libaflのload_initial_inputsはファイル名を渡さないので、1つずつ読んでSeedSourceに名前を置く
+//OriginFeedbackとAflCorpusが、orig:<名前>を付けるか、_resumeの前回の名前をそのまま使う
+//forcedなら、新しいカバレッジがなくてもすべてqueueに入れる
+fn load_seeds<E, EM, Z>(
+    state: &mut FuzzState,
+    fuzzer: &mut Z,
+    executor: &mut E,
+    manager: &mut EM,
+    dir: &Path,
+    forced: bool,
+) -> Result<(), Error>
+where
+    E: UsesState<State = FuzzState>,
+    EM: EventFirer<State = FuzzState>,
+    Z: Evaluator<E, EM, State = FuzzState>,
+{
+    let mut files = Vec::new();
+    seed_files(dir, &mut files)?;
+    //_resumeは前回のidの順に読まないと、名前を引き継げない
+    files.sort();
+
+    for path in files {
+        let input = BytesInput::from_file(&path)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        state.add_metadata(SeedSource { name });
+        let result = if forced {
+            fuzzer
+                .add_input(state, executor, manager, input)
+                .map(|_| ())
+        } else {
+            fuzzer
+                .evaluate_input(state, executor, manager, input)
+                .map(|_| ())
+        };
+        let _ = state.metadata_map_mut().remove::<SeedSource>();
+        result?;
+    }
+    Ok(())
+}
+
+//libaflと同じく、サブディレクトリも読み、隠しファイルと空のファイルは飛ばす
+fn seed_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+        let metadata = fs::metadata(&path)?;
+        if metadata.is_dir() {
+            seed_files(&path, files)?;
+        } else if metadata.is_file() && metadata.len() > 0 {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+//ブローカーと、コアごとのクライアントをforkで起動する
+//クライアントが見つけた新しいコーパスはブローカーを経由して他のクライアントに配られる
+//クライアントは落ちるとLlmpRestartingEventManagerによって再起動され、直前のstateを引き継ぐ
+fn launch(config: &Config, launcher: &LauncherConfig) -> Result<(), Error> {
+    let shmem_provider = StdShMemProvider::new()?;
+    let monitor = SimpleMonitor::with_user_monitor(|s| println!("{s}"), true);
+
+    let run_client =
+        |state, manager, core_id: CoreId| run_client(config, state, manager, Some(core_id.0));
+
+    //全クライアントが同じターゲットを実行するので、受け取ったobserverを信用して再実行を省く
+    let result = Launcher::builder()
+        .shmem_provider(shmem_provider)
+        .configuration(EventConfig::from_name("forkserver"))
+        .monitor(monitor)
+        .run_client(run_client)
+        .cores(&launcher.cores)
+        .broker_port(launcher.broker_port)
+        .build()
+        .launch();
+
+    //ブローカーはCtrl-Cで終了したとき、ShuttingDownを返す
+    match result {
+        Ok(()) | Err(Error::ShuttingDown) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+//stateはクライアントが再起動されたときに、前回のものが渡される
+//clientはLauncherのコア番号で、シングルプロセスのときはNone
+fn run_client<EM>(
+    config: &Config,
+    state: Option<FuzzState>,
+    mut manager: EM,
+    client: Option<usize>,
+) -> Result<(), Error>
+where
+    EM: for<'a> EventManager<Executor<'a, FuzzState>, FuzzerType<'a>, State = FuzzState>,
+{
+    //cur_inputなどを置くので、先にインスタンスのディレクトリを作っておく
+    let instance_dir = instance_dir(&config.dirs.output, client);
+    fs::create_dir_all(&instance_dir)?;
+
+    //CmpLogのバイナリも同じ共有メモリにカバレッジを書き込むので、大きい方に合わせる
+    let cur_input = instance_dir.join(".cur_input");
+    let mut map_size = executor::map_size(&config.target, &config.target.program, &cur_input)?;
+    if let Some(program) = &config.target.cmplog {
+        map_size = map_size.max(executor::map_size(&config.target, program, &cur_input)?);
+    }
+    let mut shmem = StdShMemProvider::new()?.new_shmem(map_size)?;
+
+    //ASanのレポートはクライアントごとのディレクトリに書き出される
+    let asan_dir = instance_dir.join(".asan");
+    let stack_observer =
+        StackHashObserver::new(STACK_NAME, asan_dir, config.objective.stack_frames)?;
+
+    //solutionを実行し直すforkserverは、カバレッジを別の共有メモリに書かせる
+    //__AFL_SHM_IDはforkserverを起動したときに読まれるので、先に起動してから自分の共有メモリに書き直す
+    let mut reproduce_shmem = match config.objective.reproduce_runs {
+        0 => None,
+        _ => Some(StdShMemProvider::new()?.new_shmem(map_size)?),
+    };
+    let reproducer = match reproduce_shmem.as_mut() {
+        Some(reproduce_shmem) => {
+            reproduce_shmem.write_to_env("__AFL_SHM_ID")?;
+            Some(executor::build(
+                &config.target,
+                &config.target.program,
+                &instance_dir.join(".cur_input_reproduce"),
+                //レポートは読まないが、同じディレクトリに書かせればStackHashObserverが次の実行で消す
+                Some(&stack_observer.log_path()),
+                map_size,
+                tuple_list!(),
+            )?)
+        }
+        None => None,
+    };
+
+    let map_observer = {
+        //afl-ccでコンパイルされたプログラムのカバレッジは、__AFL_SHM_IDの環境変数が示す共有メモリ名に保存される
+        //クライアントは別プロセスなので、クライアントごとにshmemを作り、自分の環境変数に書き込む
+        shmem.write_to_env("__AFL_SHM_ID")?;
+        let shmem_slice = shmem.as_mut_slice();
+        HitcountsMapObserver::new(unsafe { StdMapObserver::new(MAP_NAME, shmem_slice) })
+    };
+
+    let time_observer = TimeObserver::new("time");
+
+    //デフォルトでは、インデックスは追跡するが、Novelty Searchはしない
+    //MaxMapFeedback::new(&map_observer)ではなく、tracking(&map_observer, true, false)になっている理由は？
+    //広くinterestingを取りたいから？入力コーパスへの追加条件を甘くしている？
+    let map_feedback = MaxMapFeedback::tracking(
+        &map_observer,
+        config.feedback.track_indexes,
+        config.feedback.track_novelties,
+    );
+    //MaxMapFeedbackのhistoryは、observerの名前に接頭辞を付けたfeedbackの名前で保存される
+    let history_name = map_feedback.name().to_string();
+
+    //power scheduleは、キャリブレーションで測った実行時間とカバレッジの大きさからエネルギーを求める
+    //不安定なエッジを除くときも、キャリブレーションで何度か実行して調べる
+    let power = config.fuzzer.scheduler == SchedulerKind::Weighted;
+    let calibration = match (power, config.feedback.mask_unstable) {
+        (_, true) => Some(CalibrationStage::new(&map_feedback)),
+        (true, false) => Some(CalibrationStage::ignore_stability(&map_feedback)),
+        (false, false) => None,
+    };
+
+    //havoc_mutationsはスタンダードなmutationの集合
+    //名前はMOptの統計と、solutionのサイドカーに書き出すmutationに使う
+    let mutations = havoc_mutations().merge(tokens_mutations());
+    let names: Vec<String> = MutatorsTuple::<BytesInput, FuzzState>::names(&mutations)
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+    let mut feedback = corpus_feedback(map_feedback, &time_observer);
+    let mut objective = objective_feedback(
+        config,
+        &instance_dir,
+        &map_observer,
+        &stack_observer,
+        reproducer,
+        names.clone(),
+    );
+
+    let queue_dir = instance_dir.join("queue");
+    let snapshot_path = instance_dir.join(".fuzzer_state");
+
+    //再起動されたクライアントは、前回のstateを引き継ぐ
+    //プロセスを止めて再実行したときは、出力ディレクトリのスナップショットから再開する
+    let mut resumed = false;
+    let mut state = match state {
+        Some(state) => state,
+        None => {
+            prepare_resume(&instance_dir)?;
+
+            //queueはメモリ上に置きつつ、ディスクにも書き出して再開に使う
+            let corpus = AflCorpus::new(
+                InMemoryOnDiskCorpus::<BytesInput>::new(&queue_dir)?,
+                &queue_dir,
+                false,
+            )?;
+            let solutions = SolutionCorpus::new(&instance_dir)?;
+            let rand = StdRand::with_seed(current_nanos());
+            let mut state = StdState::new(rand, corpus, solutions, &mut feedback, &mut objective)?;
+            //ファイル名のtimeと、fuzzer_statsのrun_timeの起点
+            *state.start_time_mut() = current_time();
+
+            if let Some(snapshot) = Snapshot::load(&snapshot_path)? {
+                snapshot.restore(&mut state, &history_name);
+                resumed = true;
+            }
+
+            state
+        }
+    };
+
+    // feedback, objectiveはfuzzerが所有する
+    let mut fuzzer = {
+        let scheduler = IndexesLenTimeMinimizerScheduler::new(BaseScheduler::new(
+            &config.fuzzer,
+            &mut state,
+            &map_observer,
+        ));
+        StdFuzzer::new(scheduler, feedback, objective)
+    };
+
+    //CmpLogのバイナリは、__AFL_CMPLOG_SHM_IDの共有メモリにAFL++形式で比較の値を書き込む
+    let mut cmp_shmem = match &config.target.cmplog {
+        Some(_) => Some(StdShMemProvider::new()?.new_shmem(size_of::<AFLppCmpMap>())?),
+        None => None,
+    };
+
+    //AFL++とのやり取りは1つのクライアントだけが行い、取り込んだものはブローカーを経由して他のクライアントに配られる
+    let syncing = config
+        .launcher
+        .as_ref()
+        .is_none_or(|launcher| launcher.cores.ids.first().map(|core| core.0) == client);
+
+    let mut stages = {
+        //設定で選ばれたmutationだけを使うように、選択確率を調整する
+        let mutator = BaseMutator::new(&config.fuzzer, &mut state, mutations)?;
+
+        //CmpLogのバイナリは別のforkserverで起動し、トレースにだけ使う
+        let cmplog = match (&config.target.cmplog, cmp_shmem.as_mut()) {
+            (Some(program), Some(cmp_shmem)) => {
+                cmp_shmem.write_to_env("__AFL_CMPLOG_SHM_ID")?;
+                let cmp_map = unsafe { cmp_shmem.as_object_mut::<AFLppCmpMap>() };
+                let cmp_observer = AFLppCmpObserver::new(CMPLOG_NAME, cmp_map, true);
+                let cur_input = instance_dir.join(".cur_input_cmplog");
+                let executor = executor::build(
+                    &config.target,
+                    program,
+                    &cur_input,
+                    None,
+                    map_size,
+                    tuple_list!(cmp_observer),
+                )?;
+
+                //colorizationで入力のどこを変えてもカバレッジが変わらないかを調べ、
+                //RedQueenはその範囲を比較の相手の値で置き換える
+                let i2s = StdScheduledMutator::new(tuple_list!(I2SRandReplace::new()));
+                Some(tuple_list!(
+                    ColorizationStage::new(&map_observer),
+                    AFLppCmplogTracingStage::with_cmplog_observer_name(executor, CMPLOG_NAME),
+                    MultiMutationalStage::new(AFLppRedQueen::with_cmplog_options(true, true)),
+                    CmpValuesStage::new(),
+                    StdMutationalStage::new(i2s)
+                ))
+            }
+            _ => None,
+        };
+
+        tuple_list!(
+            StabilityStage::new(calibration, &history_name, config.feedback.mask_unstable),
+            CmpLogStage::new(cmplog),
+            ScheduledMutationalStage::new(power, mutator),
+            MOptStatsStage::new(names),
+            AflSyncStage::new(&config.sync, syncing, resumed)?,
+            SnapshotStage::new(snapshot_path, &history_name),
+            AflStatsStage::new(
+                instance_dir.join("fuzzer_stats"),
+                instance_dir.join("plot_data"),
+                &history_name,
+                &config.target,
+                map_size,
+                client
+            )
+        )
+    };
+
+    // observerはexecutorが所有する
+    let mut executor = {
+        let asan_log = stack_observer.log_path();
+        let observers = tuple_list!(
+            map_observer,
+            time_observer,
+            stack_observer,
+            SignalObserver::new(SIGNAL_NAME),
+            SlowObserver::new(SLOW_NAME)
+        );
+        executor::build(
+            &config.target,
+            &config.target.program,
+            &cur_input,
+            Some(&asan_log),
+            map_size,
+            observers,
+        )?
+    };
+
+    //最初のコーパスのみはディスクからロードする。以降はon-memory
+    //再開時は、historyが戻っていて新しいカバレッジにならないので、前回のqueueを強制的に追加する
+    let resume_dir = instance_dir.join(RESUME_DIR);
+    if state.corpus().count() < 1 {
+        let forced = resumed && resume_dir.is_dir();
+        let corpus_dir = if forced {
+            &resume_dir
+        } else {
+            &config.dirs.input
+        };
+        load_seeds(
+            &mut state,
+            &mut fuzzer,
+            &mut executor,
+            &mut manager,
+            corpus_dir,
+            forced,
+        )
+        .map_err(|err| {
+            Error::illegal_state(format!(
+                "Failed to load initial corpus at {:?}: {:?}",
+                corpus_dir, err
+            ))
+        })?;
+
+        if forced {
+            fs::remove_dir_all(&resume_dir)?;
+        }
+    }
+
+    //再起動したクライアントも、引き継いだコーパスから求め直す
+    if let Timeout::Auto { multiplier, max } = config.target.timeout {
+        let timeout = timeout::from_corpus(&state, multiplier, max)?;
+        executor.set_timeout(timeout);
+        state.add_metadata(AutoTimeout { timeout });
+        manager.fire(
+            &mut state,
+            Event::UpdateUserStats {
+                name: "timeout".to_string(),
+                value: UserStats::String(format!("{} ms", timeout.as_millis())),
+                phantom: PhantomData,
+            },
+        )?;
+    }
+
+    if state.metadata_map().get::<Tokens>().is_none() {
+        let mut tokens = Tokens::new();
+
+        for path in &config.dirs.dictionaries {
+            load_dictionary(&mut tokens, path)?;
+        }
+
+        state.add_metadata(tokens);
+    }
+
+    //再開したときは、前回までに見つけたクラッシュ・ハングの数をモニタに出しておく
+    if resumed {
+        let counts = state.metadata::<SolutionCounts>()?.clone();
+        for (kind, count) in [
+            (SolutionKind::Crash, counts.crashes),
+            (SolutionKind::Hang, counts.hangs),
+        ] {
+            manager.fire(
+                &mut state,
+                Event::UpdateUserStats {
+                    name: kind.stat_name().to_string(),
+                    value: UserStats::Number(count),
+                    phantom: PhantomData,
+                },
+            )?;
+        }
+    }
+
+    //ターゲットが共有メモリでの受け渡しに対応していたかを、モニタの表示に出す
+    let delivery = if executor.uses_shmem_testcase() {
+        "shmem"
+    } else {
+        match (config.target.delivery, config.target.input_file) {
+            (Delivery::File, InputFileKind::Disk) => "file",
+            (Delivery::File, InputFileKind::Memfd) => "memfd",
+            (Delivery::Stdin, _) => "stdin",
+        }
+    };
+    manager.fire(
+        &mut state,
+        Event::UpdateUserStats {
+            name: "delivery".to_string(),
+            value: UserStats::String(delivery.to_string()),
+            phantom: PhantomData,
+        },
+    )?;
+
+    //どのpower scheduleで動いているかを、モニタの表示に出す
+    if power {
+        let schedule = format!("{:?}", config.fuzzer.schedule).to_lowercase();
+        manager.fire(
+            &mut state,
+            Event::UpdateUserStats {
+                name: "schedule".to_string(),
+                value: UserStats::String(schedule),
+                phantom: PhantomData,
+            },
+        )?;
+    }
+
+    fuzzer.fuzz_loop(&mut stages, &mut executor, &mut state, &mut manager)?;
+    Ok(())
+}
+
+//新しいカバレッジであるとき、入力コーパスに追加する
+//クラッシュ・ハングはobjectiveが判断するので、solutionにならなかったものも含めてqueueには入れない
+//なおtime_feedbackは、必ずfalseであるので、条件判定に寄与しない
+//ただし、条件判定に寄与しないものの、Testcaseに実行時間のメタデータを付与してくれる
+//SlowFeedbackも同じく、ハングでなかった遅い入力に印を付けるだけ
+//OriginFeedbackは、queueのファイル名に入れるmutationなどを付ける
+fn corpus_feedback<'a>(
+    map_feedback: MaxMapFeedback<CoverageObserver<'a>, FuzzState, u8>,
+    time_observer: &TimeObserver,
+) -> CorpusFeedback<'a> {
+    let time_feedback = TimeFeedback::with_observer(time_observer);
+    feedback_and_fast!(
+        ExitOkFeedback::new(),
+        feedback_or!(
+            map_feedback,
+            time_feedback,
+            SlowFeedback::new(),
+            OriginFeedback::new()
+        )
+    )
+}
+
+//デフォルトでは、クラッシュし、かつ新しいカバレッジであるとき、Bugだと判断する
+//ConstFeedbackで、設定に応じて各条件を有効・無効にする
+fn objective_feedback<'a>(
+    config: &Config,
+    instance_dir: &Path,
+    map_observer: &CoverageObserver<'a>,
+    stack_observer: &StackHashObserver,
+    reproducer: Option<ReproduceExecutor<FuzzState>>,
+    names: Vec<String>,
+) -> ObjectiveFeedback<'a> {
+    let crash_feedback = feedback_and_fast!(
+        ConstFeedback::new(config.objective.crash),
+        CrashFeedback::new()
+    );
+    let timeout_feedback = feedback_and_fast!(
+        ConstFeedback::new(config.objective.timeout),
+        TimeoutFeedback::new()
+    );
+    let unique_feedback = UniquenessFeedback::new(
+        config.objective.unique,
+        map_observer,
+        stack_observer,
+        instance_dir.join("crashes"),
+    );
+    let reproduce_feedback = ReproduceFeedback::new(
+        reproducer,
+        config.objective.reproduce_runs,
+        config.objective.discard_unreproducible,
+    );
+    //solutionになる実行だけが最後まで評価され、クラッシュかハングかと、見つけたときの状況を記録する
+    //再現しなかったものを捨てるときは、数える前に捨てる
+    feedback_and_fast!(
+        feedback_or_fast!(crash_feedback, timeout_feedback),
+        unique_feedback,
+        reproduce_feedback,
+        SolutionKindFeedback::new(),
+        SolutionInfoFeedback::new(&config.target, names)
+    )
+}
+
+//Launcherでは、afl-fuzzの-Sのようにクライアントごとのインスタンスのディレクトリ(<output>/client_<コア番号>)を使う
+//queueやcrashesのidはディレクトリごとの通し番号なので、クライアント間で重ならず、afl-whatsupやafl-plotでそのまま読める
+//シングルプロセスのときは、出力ディレクトリをそのまま使う
+fn instance_dir(output: &Path, client: Option<usize>) -> PathBuf {
+    match client {
+        Some(client) => output.join(format!("{INSTANCE_PREFIX}_{client}")),
+        None => output.to_path_buf(),
+    }
+}
+
+//afl-fuzzの-xと同様に、ファイルならAFL形式の辞書として読み込む
+//ディレクトリなら、中の各ファイルの内容をそのまま1つのトークンとして扱う
+fn load_dictionary(tokens: &mut Tokens, path: &Path) -> Result<(), Error> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_file() {
+                tokens.add_token(&fs::read(entry.path())?);
+            }
+        }
+    } else {
+        tokens.add_from_file(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use libafl::{
+        events::NopEventManager, executors::ExitKind, fuzzer::ExecutionProcessor,
+        observers::ObserversTuple, schedulers::QueueScheduler, state::HasSolutions,
+    };
+
+    use libafl_bolts::tuples::MatchName;
+
+    use super::*;
+    use crate::{cli::Options, config::UniquenessPolicy, runner::WorkDir};
+
+    type TestFuzzer = StdFuzzer<
+        QueueScheduler<FuzzState>,
+        CorpusFeedback<'static>,
+        ObjectiveFeedback<'static>,
+        Observers<'static>,
+    >;
+
+    fn map_observer(edge: usize) -> CoverageObserver<'static> {
+        let mut map = vec![0; 64];
+        map[edge] = 1;
+        HitcountsMapObserver::new(StdMapObserver::owned(MAP_NAME, map))
+    }
+
+    fn observers(edge: usize, asan_dir: &Path) -> Observers<'static> {
+        tuple_list!(
+            map_observer(edge),
+            TimeObserver::new("time"),
+            StackHashObserver::new(STACK_NAME, asan_dir.to_path_buf(), 5).unwrap(),
+            SignalObserver::new(SIGNAL_NAME),
+            SlowObserver::new(SLOW_NAME)
+        )
+    }
+
+    fn load_config(work_dir: &WorkDir, output: &Path) -> Config {
+        let options = Options::parse_from([
+            "libafl-sample".as_ref(),
+            "-i".as_ref(),
+            work_dir.path().as_os_str(),
+            "-o".as_ref(),
+            output.as_os_str(),
+            "--".as_ref(),
+            "./target".as_ref(),
+        ]);
+        Config::load(&options).unwrap()
+    }
+
+    fn new_fuzzer(config: &Config, output: &Path, asan_dir: &Path) -> (FuzzState, TestFuzzer) {
+        let map_observer = map_observer(0);
+        let stack_observer = StackHashObserver::new(STACK_NAME, asan_dir.to_path_buf(), 5).unwrap();
+        let mut feedback = corpus_feedback(
+            MaxMapFeedback::tracking(&map_observer, true, false),
+            &TimeObserver::new("time"),
+        );
+        let mut objective =
+            objective_feedback(config, output, &map_observer, &stack_observer, None, vec![]);
+
+        let queue_dir = output.join("queue");
+        let corpus = AflCorpus::new(
+            InMemoryOnDiskCorpus::new(&queue_dir).unwrap(),
+            &queue_dir,
+            false,
+        )
+        .unwrap();
+        let solutions = SolutionCorpus::new(output).unwrap();
+        let state = StdState::new(
+            StdRand::with_seed(0),
+            corpus,
+            solutions,
+            &mut feedback,
+            &mut objective,
+        )
+        .unwrap();
+        (
+            state,
+            StdFuzzer::new(QueueScheduler::new(), feedback, objective),
+        )
+    }
+
+    //new-coverageで重複として捨てたクラッシュは、コーパスのhistoryにとって新しいエッジでもqueueに入れない
+    #[test]
+    fn duplicate_crash_is_not_queued() {
+        let work_dir = WorkDir::new("fuzz-test-duplicate-crash").unwrap();
+        let output = work_dir.path().join("out");
+        let asan_dir = work_dir.path().join(".asan");
+        let config = load_config(&work_dir, &output);
+        let (mut state, mut fuzzer) = new_fuzzer(&config, &output, &asan_dir);
+        let mut manager = NopEventManager::new();
+        let input = BytesInput::new(b"FUZZ".to_vec());
+
+        let mut run = |state: &mut FuzzState, exit_kind| {
+            fuzzer
+                .process_execution(
+                    state,
+                    &mut manager,
+                    input.clone(),
+                    &observers(3, &asan_dir),
+                    &exit_kind,
+                    false,
+                )
+                .unwrap();
+        };
+
+        //1つ目のクラッシュはsolutionになる
+        run(&mut state, ExitKind::Crash);
+        assert_eq!(state.solutions().count(), 1);
+        assert_eq!(state.corpus().count(), 0);
+
+        //同じカバレッジのクラッシュはsolutionにもqueueにもならない
+        run(&mut state, ExitKind::Crash);
+        assert_eq!(state.solutions().count(), 1);
+        assert_eq!(state.corpus().count(), 0);
+
+        //正常に終了したものは、同じエッジでもqueueに入る
+        run(&mut state, ExitKind::Ok);
+        assert_eq!(state.corpus().count(), 1);
+    }
+
+    //既定の設定では、タイムアウトした実行はクラッシュとは別にhangs/に保存される
+    #[test]
+    fn timeout_is_saved_to_hangs_by_default() {
+        let work_dir = WorkDir::new("fuzz-test-hangs").unwrap();
+        let output = work_dir.path().join("out");
+        let asan_dir = work_dir.path().join(".asan");
+        let config = load_config(&work_dir, &output);
+        assert!(config.objective.timeout);
+        let (mut state, mut fuzzer) = new_fuzzer(&config, &output, &asan_dir);
+        let mut manager = NopEventManager::new();
+
+        fuzzer
+            .process_execution(
+                &mut state,
+                &mut manager,
+                BytesInput::new(b"SLOW".to_vec()),
+                &observers(3, &asan_dir),
+                &ExitKind::Timeout,
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(state.solutions().count(), 1);
+        assert_eq!(state.corpus().count(), 0);
+        let hangs = fs::read_dir(output.join("hangs"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| !name.starts_with('.'))
+            .collect::<Vec<_>>();
+        assert_eq!(hangs.len(), 1, "{hangs:?}");
+        assert!(hangs[0].starts_with("id:000000,"), "{hangs:?}");
+        let counts = state.metadata::<SolutionCounts>().unwrap();
+        assert_eq!((counts.crashes, counts.hangs), (0, 1));
+    }
+
+    //new-coverageのhistoryはクラッシュとハングで別なので、クラッシュと同じエッジのハングも保存される
+    #[test]
+    fn hang_with_crash_coverage_is_saved() {
+        let work_dir = WorkDir::new("fuzz-test-kinds").unwrap();
+        let output = work_dir.path().join("out");
+        let asan_dir = work_dir.path().join(".asan");
+        let config = load_config(&work_dir, &output);
+        let (mut state, mut fuzzer) = new_fuzzer(&config, &output, &asan_dir);
+        let mut manager = NopEventManager::new();
+
+        for exit_kind in [ExitKind::Crash, ExitKind::Timeout, ExitKind::Timeout] {
+            fuzzer
+                .process_execution(
+                    &mut state,
+                    &mut manager,
+                    BytesInput::new(b"FUZZ".to_vec()),
+                    &observers(3, &asan_dir),
+                    &exit_kind,
+                    false,
+                )
+                .unwrap();
+        }
+
+        //2つ目のハングは、1つ目のハングと同じカバレッジなので捨てる
+        assert_eq!(state.solutions().count(), 2);
+        let counts = state.metadata::<SolutionCounts>().unwrap();
+        assert_eq!((counts.crashes, counts.hangs), (1, 1));
+    }
+
+    //stack-hashのsolutionはAFL++の名前で保存され、重複したヒットは隠しファイルに記録される
+    #[test]
+    fn duplicate_stack_is_logged_beside_solution() {
+        let work_dir = WorkDir::new("fuzz-test-stack-hits").unwrap();
+        let output = work_dir.path().join("out");
+        let asan_dir = work_dir.path().join(".asan");
+        let mut config = load_config(&work_dir, &output);
+        config.objective.unique = UniquenessPolicy::StackHash;
+        let (mut state, mut fuzzer) = new_fuzzer(&config, &output, &asan_dir);
+        let mut manager = NopEventManager::new();
+
+        //エッジが違っても、スタックが同じなら同じバケット
+        for edge in [3, 4] {
+            let input = BytesInput::new(vec![b'A'; edge]);
+            let mut observers = observers(edge, &asan_dir);
+            fs::write(
+                asan_dir.join("asan.1234"),
+                "==1234==ERROR: AddressSanitizer: heap-buffer-overflow\n    \
+                 #0 0x55d0c1 in parse /src/target.c:12:5\n    \
+                 #1 0x55d0d2 in main /src/target.c:27:39\n",
+            )
+            .unwrap();
+            observers
+                .match_name_mut::<StackHashObserver>(STACK_NAME)
+                .unwrap()
+                .set_pid(1234);
+            observers
+                .post_exec_all(&mut state, &input, &ExitKind::Crash)
+                .unwrap();
+            fuzzer
+                .process_execution(
+                    &mut state,
+                    &mut manager,
+                    input,
+                    &observers,
+                    &ExitKind::Crash,
+                    false,
+                )
+                .unwrap();
+        }
+        assert_eq!(state.solutions().count(), 1);
+
+        let mut names = fs::read_dir(output.join("crashes"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        let solution = names
+            .iter()
+            .find(|name| !name.starts_with('.'))
+            .unwrap()
+            .clone();
+        assert!(solution.starts_with("id:000000,"), "{solution}");
+        assert!(names.contains(&format!(".{solution}.hits")), "{names:?}");
+        assert!(names
+            .iter()
+            .all(|name| name.starts_with('.') || *name == solution));
+
+        let sidecar =
+            fs::read_to_string(output.join("crashes").join(format!(".{solution}.json"))).unwrap();
+        assert!(sidecar.contains("\"stack-hash\""), "{sidecar}");
+    }
+}
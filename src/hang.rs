@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::UsesInput,
+    observers::ObserversTuple,
+    state::{HasClientPerfMonitor, HasMetadata},
+    Error,
+};
+use libafl_bolts::{impl_serdeany, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::executor::{SlowObserver, SLOW_NAME};
+
+//タイムアウトしたが、hang_timeoutで実行し直すと終わった入力に付ける
+//timeoutは1回目に超えたタイムアウトで、実行時間はTimeFeedbackがTestcaseに付けている
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowMetadata {
+    pub timeout: Duration,
+}
+
+impl_serdeany!(SlowMetadata);
+
+//コーパスのfeedbackにfeedback_or!でつなぎ、コーパスに追加される入力が遅いものかを記録する
+//TimeFeedbackと同じく、条件判定には寄与しない
+#[derive(Debug, Default)]
+pub struct SlowFeedback;
+
+impl SlowFeedback {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for SlowFeedback {
+    fn name(&self) -> &str {
+        "SlowFeedback"
+    }
+}
+
+impl<S> Feedback<S> for SlowFeedback
+where
+    S: UsesInput + HasClientPerfMonitor,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(false)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        _state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let timeout = observers
+            .match_name::<SlowObserver>(SLOW_NAME)
+            .and_then(SlowObserver::timeout);
+        if let Some(timeout) = timeout {
+            testcase.add_metadata(SlowMetadata { timeout });
+        }
+        Ok(())
+    }
+}
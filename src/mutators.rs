@@ -0,0 +1,368 @@
+use std::{cmp::Reverse, iter, marker::PhantomData};
+
+use libafl::{
+    corpus::CorpusId,
+    events::{Event, EventFirer},
+    monitors::UserStats,
+    mutators::{
+        mopt_mutator::MOpt,
+        scheduled::{HavocCrossoverType, HavocMutationsNoCrossoverType},
+        ComposedByMutations, MutationId, MutationResult, Mutator, MutatorsTuple, ScheduledMutator,
+        StdMOptMutator, TokenInsert, TokenReplace, TuneableScheduledMutator,
+    },
+    stages::Stage,
+    state::{HasCorpus, HasMetadata, HasRand, HasSolutions, UsesState},
+    Error,
+};
+use libafl_bolts::{
+    impl_serdeany,
+    tuples::{tuple_list_type, HasConstLen},
+    Named,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{FuzzerConfig, MutatorKind};
+
+//StdMOptMutatorに渡すパラメータで、libaflのサンプルと同じ値
+//一度に重ねるmutationの数は最大2^7、パイロットモードで試すswarmの数
+const MOPT_MAX_STACK_POW: u64 = 7;
+const MOPT_SWARM_NUM: usize = 5;
+
+//tokens_mutations()の型
+type TokensMutationsType = tuple_list_type!(TokenInsert, TokenReplace);
+
+//havoc_mutations().merge(tokens_mutations())の各mutationを選ぶ確率を求める
+//TuneableScheduledMutatorに渡すので、並びはmutationの並びと一致させる
+//設定で選ばれていないmutationは確率0にして、残りで等分する
+pub fn mutation_probabilities(kinds: &[MutatorKind]) -> Vec<f32> {
+    let groups = [
+        (MutatorKind::Havoc, HavocMutationsNoCrossoverType::LEN),
+        (MutatorKind::Crossover, HavocCrossoverType::LEN),
+        (MutatorKind::Tokens, TokensMutationsType::LEN),
+    ];
+
+    let enabled: usize = groups
+        .iter()
+        .filter(|(kind, _)| kinds.contains(kind))
+        .map(|(_, len)| len)
+        .sum();
+
+    groups
+        .iter()
+        .flat_map(|(kind, len)| {
+            let probability = if kinds.contains(kind) {
+                1.0 / enabled as f32
+            } else {
+                0.0
+            };
+            iter::repeat_n(probability, *len)
+        })
+        .collect()
+}
+
+//直前のmutateで適用したmutationの番号で、solutionのサイドカーに書き出す
+//実行を評価し終わるとpost_execで空にするので、mutationを通らないsolutionには残らない
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AppliedMutations {
+    pub ids: Vec<usize>,
+}
+
+impl_serdeany!(AppliedMutations);
+
+//設定でmutationの選び方を切り替えられるように、列挙型でまとめる
+//Tuneableは設定で選んだmutationを固定の確率で選び、
+//MOptは新しいコーパスやsolutionを見つけたmutationの確率を上げていく
+pub enum BaseMutator<I, MT, S>
+where
+    MT: MutatorsTuple<I, S>,
+    S: HasRand + HasMetadata + HasCorpus + HasSolutions,
+{
+    Tuneable(TuneableScheduledMutator<I, MT, S>),
+    MOpt(StdMOptMutator<I, MT, S>),
+}
+
+impl<I, MT, S> BaseMutator<I, MT, S>
+where
+    MT: MutatorsTuple<I, S>,
+    S: HasRand + HasMetadata + HasCorpus + HasSolutions,
+{
+    //どちらも確率などの状態をstateのメタデータに保存するので、stateを受け取る
+    pub fn new(config: &FuzzerConfig, state: &mut S, mutations: MT) -> Result<Self, Error> {
+        if config.mopt {
+            let mutator =
+                StdMOptMutator::new(state, mutations, MOPT_MAX_STACK_POW, MOPT_SWARM_NUM)?;
+            return Ok(Self::MOpt(mutator));
+        }
+
+        let mutator = TuneableScheduledMutator::new(state, mutations);
+        TuneableScheduledMutator::set_mutation_probabilities(
+            state,
+            mutation_probabilities(&config.mutators),
+        )?;
+        Ok(Self::Tuneable(mutator))
+    }
+}
+
+impl<I, MT, S> Named for BaseMutator<I, MT, S>
+where
+    MT: MutatorsTuple<I, S>,
+    S: HasRand + HasMetadata + HasCorpus + HasSolutions,
+{
+    fn name(&self) -> &str {
+        match self {
+            Self::Tuneable(mutator) => mutator.name(),
+            Self::MOpt(mutator) => mutator.name(),
+        }
+    }
+}
+
+impl<I, MT, S> Mutator<I, S> for BaseMutator<I, MT, S>
+where
+    MT: MutatorsTuple<I, S>,
+    S: HasRand + HasMetadata + HasCorpus + HasSolutions,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut I,
+        stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        let (result, ids) = match self {
+            //TuneableScheduledMutatorのmutateと同じ手順で、選んだmutationを順に記録する
+            Self::Tuneable(mutator) => {
+                let mut result = MutationResult::Skipped;
+                let mut ids = Vec::new();
+                for _ in 0..mutator.iterations(state, input) {
+                    let idx = mutator.schedule(state, input);
+                    let outcome = mutator
+                        .mutations_mut()
+                        .get_and_mutate(idx, state, input, stage_idx)?;
+                    if outcome == MutationResult::Mutated {
+                        result = MutationResult::Mutated;
+                    }
+                    ids.push(mutation_index(idx, MT::LEN));
+                }
+                (result, ids)
+            }
+            //MOptは選んだmutationを外に出さないので、mutationごとの実行回数の差から求める
+            //適用した順番は分からないので、番号順に並ぶ
+            Self::MOpt(mutator) => {
+                let before = mopt_cycles(state)?;
+                let result = mutator.mutate(state, input, stage_idx)?;
+                let after = mopt_cycles(state)?;
+                let ids = before
+                    .iter()
+                    .zip(&after)
+                    .enumerate()
+                    .flat_map(|(id, (before, after))| iter::repeat_n(id, (after - before) as usize))
+                    .collect();
+                (result, ids)
+            }
+        };
+
+        state.add_metadata(AppliedMutations { ids });
+        Ok(result)
+    }
+
+    fn post_exec(
+        &mut self,
+        state: &mut S,
+        stage_idx: i32,
+        corpus_idx: Option<CorpusId>,
+    ) -> Result<(), Error> {
+        if let Ok(applied) = state.metadata_mut::<AppliedMutations>() {
+            applied.ids.clear();
+        }
+
+        match self {
+            Self::Tuneable(mutator) => mutator.post_exec(state, stage_idx, corpus_idx),
+            Self::MOpt(mutator) => mutator.post_exec(state, stage_idx, corpus_idx),
+        }
+    }
+}
+
+//MutationIdの番号はlibaflの外から読めないので、番号を順に比べて求める
+fn mutation_index(id: MutationId, len: usize) -> usize {
+    (0..len)
+        .find(|index| MutationId::from(*index) == id)
+        .unwrap_or(len)
+}
+
+//コアモードと全swarmのパイロットモードで、各mutationを実行した回数の合計
+fn mopt_cycles<S: HasMetadata>(state: &S) -> Result<Vec<u64>, Error> {
+    let mopt = state.metadata::<MOpt>()?;
+    let mut cycles = mopt.core_operator_cycles_v2.clone();
+    for swarm in &mopt.pilot_operator_cycles_v2 {
+        for (total, count) in cycles.iter_mut().zip(swarm) {
+            *total += count;
+        }
+    }
+    Ok(cycles)
+}
+
+//MOptが各mutationに数えた発見の数を、"mopt"のユーザー統計としてモニタに送る
+//発見はそのとき重ねた全てのmutationに数えられるので、合計は発見の数より大きくなる
+//発見が増えたときだけ送り、MOptを使っていなければ何もしない
+pub struct MOptStatsStage<E, EM, Z> {
+    names: Vec<String>,
+    last_finds: usize,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, Z> MOptStatsStage<E, EM, Z> {
+    //namesはmutationの並びと一致させる
+    pub fn new(names: Vec<String>) -> Self {
+        Self {
+            names,
+            last_finds: 0,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, EM, Z> UsesState for MOptStatsStage<E, EM, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for MOptStatsStage<E, EM, Z>
+where
+    E: UsesState,
+    E::State: HasMetadata,
+    EM: EventFirer<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+        _corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        let Some(mopt) = state.metadata_map().get::<MOpt>() else {
+            return Ok(());
+        };
+        if mopt.total_finds == self.last_finds {
+            return Ok(());
+        }
+        self.last_finds = mopt.total_finds;
+
+        //コアモードとパイロットモードの全swarmの発見を足し合わせる
+        let mut finds = mopt.core_operator_finds_v2.clone();
+        for swarm in &mopt.pilot_operator_finds_v2 {
+            for (total, count) in finds.iter_mut().zip(swarm) {
+                *total += count;
+            }
+        }
+
+        //BytesDeleteMutatorのように同じ名前のmutationが複数あるので、名前ごとにまとめる
+        let mut counts: Vec<(&str, u64)> = Vec::new();
+        for (name, count) in self.names.iter().zip(finds) {
+            match counts.iter_mut().find(|(other, _)| other == name) {
+                Some((_, total)) => *total += count,
+                None => counts.push((name, count)),
+            }
+        }
+        counts.retain(|(_, count)| *count != 0);
+        counts.sort_by_key(|(_, count)| Reverse(*count));
+
+        let value = counts
+            .iter()
+            .map(|(name, count)| format!("{name}={count}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: "mopt".to_string(),
+                value: UserStats::String(value),
+                phantom: PhantomData,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::{
+        inputs::BytesInput,
+        mutators::{havoc_mutations, tokens_mutations},
+    };
+    use libafl_bolts::tuples::Merge;
+
+    use super::*;
+    use crate::runner::RunnerState;
+
+    //fuzz.rsと同じ並びのmutationの名前
+    fn names() -> Vec<String> {
+        let mutations = havoc_mutations().merge(tokens_mutations());
+        MutatorsTuple::<BytesInput, RunnerState>::names(&mutations)
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    fn assert_normalized(probabilities: &[f32]) {
+        let sum: f32 = probabilities.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-5, "sum is {sum}");
+    }
+
+    #[test]
+    fn probabilities_line_up_with_mutations() {
+        let all = [
+            MutatorKind::Havoc,
+            MutatorKind::Crossover,
+            MutatorKind::Tokens,
+        ];
+        let probabilities = mutation_probabilities(&all);
+        assert_eq!(probabilities.len(), names().len());
+        assert_normalized(&probabilities);
+
+        //すべて選べば等分になる
+        let first = probabilities[0];
+        assert!(probabilities
+            .iter()
+            .all(|probability| *probability == first));
+    }
+
+    //選ばれていない種類は0になり、残りで1を等分する
+    #[test]
+    fn probabilities_respect_the_configured_kinds() {
+        let names = names();
+        for kinds in [
+            vec![MutatorKind::Havoc],
+            vec![MutatorKind::Crossover],
+            vec![MutatorKind::Tokens],
+            vec![MutatorKind::Havoc, MutatorKind::Tokens],
+            vec![MutatorKind::Crossover, MutatorKind::Tokens],
+        ] {
+            let probabilities = mutation_probabilities(&kinds);
+            assert_normalized(&probabilities);
+
+            let enabled = probabilities
+                .iter()
+                .filter(|probability| **probability > 0.0)
+                .collect::<Vec<_>>();
+            assert!(enabled.iter().all(|probability| *probability == enabled[0]));
+
+            for (name, probability) in names.iter().zip(&probabilities) {
+                let kind = if name.contains("Crossover") {
+                    MutatorKind::Crossover
+                } else if name.starts_with("Token") {
+                    MutatorKind::Tokens
+                } else {
+                    MutatorKind::Havoc
+                };
+                assert_eq!(
+                    *probability > 0.0,
+                    kinds.contains(&kind),
+                    "{name} with {kinds:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::marker::PhantomData;
+
+use libafl::{
+    corpus::{Corpus, CorpusId},
+    executors::{Executor, HasObservers},
+    fuzzer::Evaluator,
+    mutators::Mutator,
+    schedulers::{testcase_score::CorpusPowerTestcaseScore, TestcaseScore},
+    stages::{mutational::DEFAULT_MUTATIONAL_MAX_ITERATIONS, MutationalStage, Stage},
+    state::{HasClientPerfMonitor, HasCorpus, HasMetadata, HasRand, UsesState},
+    Error,
+};
+use libafl_bolts::rands::Rand;
+
+//スケジューラに合わせてmutationの回数を決めるステージ
+//powerのとき(weighted)は、キャリブレーションで測った実行時間とカバレッジから求めた
+//power scheduleのエネルギーを回数にする(StdPowerMutationalStageと同じ)
+//そうでないときはStdMutationalStageと同じく、1から128回のランダムな回数にする
+//どちらを選んでもステージの型が変わらないので、同じコードで組み立てられる
+pub struct ScheduledMutationalStage<E, EM, M, Z> {
+    power: bool,
+    mutator: M,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, M, Z> ScheduledMutationalStage<E, EM, M, Z> {
+    //powerのときは、このステージより前でCalibrationStageを実行しておく
+    pub fn new(power: bool, mutator: M) -> Self {
+        Self {
+            power,
+            mutator,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, EM, M, Z> UsesState for ScheduledMutationalStage<E, EM, M, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, M, Z> MutationalStage<E, EM, E::Input, M, Z> for ScheduledMutationalStage<E, EM, M, Z>
+where
+    E: Executor<EM, Z> + HasObservers,
+    EM: UsesState<State = E::State>,
+    M: Mutator<E::Input, E::State>,
+    E::State: HasClientPerfMonitor + HasCorpus + HasMetadata + HasRand,
+    Z: Evaluator<E, EM, State = E::State>,
+{
+    fn mutator(&self) -> &M {
+        &self.mutator
+    }
+
+    fn mutator_mut(&mut self) -> &mut M {
+        &mut self.mutator
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn iterations(&self, state: &mut E::State, corpus_idx: CorpusId) -> Result<u64, Error> {
+        if !self.power {
+            return Ok(1 + state.rand_mut().below(DEFAULT_MUTATIONAL_MAX_ITERATIONS));
+        }
+
+        let mut testcase = state.corpus().get(corpus_idx)?.borrow_mut();
+        let score = CorpusPowerTestcaseScore::compute(state, &mut testcase)?;
+        Ok(score as u64)
+    }
+}
+
+impl<E, EM, M, Z> Stage<E, EM, Z> for ScheduledMutationalStage<E, EM, M, Z>
+where
+    E: Executor<EM, Z> + HasObservers,
+    EM: UsesState<State = E::State>,
+    M: Mutator<E::Input, E::State>,
+    E::State: HasClientPerfMonitor + HasCorpus + HasMetadata + HasRand,
+    Z: Evaluator<E, EM, State = E::State>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut E::State,
+        manager: &mut EM,
+        corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        self.perform_mutational(fuzzer, executor, state, manager, corpus_idx)
+    }
+}
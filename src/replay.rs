@@ -0,0 +1,72 @@
+use libafl::{
+    executors::ExitKind,
+    inputs::{BytesInput, Input},
+    Error,
+};
+use nix::sys::signal::Signal;
+
+use crate::{
+    cli::ReplayOptions,
+    config::ToolConfig,
+    runner::{self, Execution, Runner, WorkDir},
+};
+
+//ファジングと同じexecutorで入力を1回ずつ実行し、結果を1行ずつ表示する
+//修正後にcrashes/を流して、クラッシュしなくなったことを確かめるのに使う
+pub fn replay(config: &ToolConfig, options: &ReplayOptions) -> Result<(), Error> {
+    let files = runner::input_files(&options.input)?;
+
+    let work_dir = WorkDir::new("replay")?;
+    let mut shmem = runner::coverage_shmem(config, &work_dir)?;
+    let mut runner = Runner::new(config, &mut shmem, &work_dir)?;
+
+    let (mut ok, mut crashes, mut timeouts) = (0, 0, 0);
+    for path in &files {
+        let input = BytesInput::from_file(path)?;
+        let execution = runner.run(&input)?;
+
+        match execution.exit_kind {
+            ExitKind::Ok => ok += 1,
+            ExitKind::Timeout => timeouts += 1,
+            _ => crashes += 1,
+        }
+        println!("{}: {}", path.display(), describe(&execution));
+    }
+
+    if files.len() > 1 {
+        println!(
+            "{} inputs: {ok} ok, {crashes} crash, {timeouts} timeout",
+            files.len()
+        );
+    }
+    Ok(())
+}
+
+//"crash (SIGABRT), 0.412 ms, 5 edges"のように表示する
+//hang_timeoutで実行し直して終わったときは、"ok (slow, over 200 ms)"のようにする
+fn describe(execution: &Execution) -> String {
+    let kind = match execution.exit_kind {
+        ExitKind::Ok => "ok".to_string(),
+        ExitKind::Timeout => "timeout".to_string(),
+        ExitKind::Crash => match execution.signal {
+            Some(signal) => match Signal::try_from(signal) {
+                Ok(name) => format!("crash ({name})"),
+                Err(_) => format!("crash (signal {signal})"),
+            },
+            None => "crash".to_string(),
+        },
+        exit_kind => format!("{exit_kind:?}").to_lowercase(),
+    };
+
+    let time = match execution.exec_time {
+        Some(time) => format!("{:.3} ms", time.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    };
+
+    let kind = match execution.slow {
+        Some(timeout) => format!("{kind} (slow, over {} ms)", timeout.as_millis()),
+        None => kind,
+    };
+
+    format!("{kind}, {time}, {} edges", execution.coverage.len())
+}
@@ -0,0 +1,148 @@
+use std::fmt::Debug;
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::{forkserver::HasForkserver, ExitKind, HasObservers},
+    feedbacks::Feedback,
+    inputs::{HasTargetBytes, UsesInput},
+    observers::ObserversTuple,
+    state::{HasClientPerfMonitor, HasExecutions, HasMetadata, UsesState},
+    Error,
+};
+use libafl_bolts::{impl_serdeany, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{executor::TargetExecutor, solutions::SolutionKind, timeout::AutoTimeout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Verdict {
+    //実行し直すたびに、同じ種類のBugになった
+    Reproducible,
+    //競合やASLRなどで、再現したりしなかったりする
+    Flaky,
+}
+
+//solutionのTestcaseに付け、.metadataのファイルにも書き出される
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reproducibility {
+    pub verdict: Verdict,
+    pub runs: usize,
+    pub reproduced: usize,
+    pub rate: f64,
+}
+
+impl_serdeany!(Reproducibility);
+
+//solutionになる入力を、保存する前に何度か実行し直し、同じ種類のBugになった回数を記録する
+//objectiveのUniquenessFeedbackの後につなぐので、重複として捨てるものは実行し直さない
+//discardなら、一度も再現しなかったものはsolutionにせず、保存も数えもしない
+//ハングの実行し直しは、1回ごとにタイムアウトまで待つので時間がかかる
+//executorがなければ(reproduce_runsが0なら)、何もせずに通す
+#[derive(Debug)]
+pub struct ReproduceFeedback<E> {
+    //fuzzerのexecutorとは別のforkserverで、カバレッジも別の共有メモリに書かせる
+    //同じ共有メモリだと、objectiveとfeedbackが見ているobserverのマップが上書きされる
+    executor: Option<E>,
+    runs: usize,
+    discard: bool,
+    last: Option<Reproducibility>,
+}
+
+impl<E> ReproduceFeedback<E> {
+    pub fn new(executor: Option<E>, runs: usize, discard: bool) -> Self {
+        Self {
+            executor,
+            runs,
+            discard,
+            last: None,
+        }
+    }
+}
+
+impl<E> Named for ReproduceFeedback<E> {
+    fn name(&self) -> &str {
+        "ReproduceFeedback"
+    }
+}
+
+impl<E, S> Feedback<S> for ReproduceFeedback<TargetExecutor<E>>
+where
+    E: HasForkserver + HasObservers + UsesState<State = S> + Debug,
+    S: UsesInput + HasClientPerfMonitor + HasExecutions + HasMetadata + Debug,
+    S::Input: HasTargetBytes,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        let (Some(executor), Some(kind)) =
+            (&mut self.executor, SolutionKind::from_exit_kind(exit_kind))
+        else {
+            return Ok(true);
+        };
+
+        //autoのタイムアウトは、fuzzerのexecutorと同じものを使う
+        if let Some(auto) = state.metadata_map().get::<AutoTimeout>() {
+            executor.set_timeout(auto.timeout);
+        }
+
+        let mut reproduced = 0;
+        for _ in 0..self.runs {
+            let exit_kind = executor.run(state, input)?;
+            *state.executions_mut() += 1;
+
+            if SolutionKind::from_exit_kind(&exit_kind) == Some(kind) {
+                reproduced += 1;
+            }
+        }
+
+        if reproduced == 0 && self.discard {
+            return Ok(false);
+        }
+
+        let verdict = if reproduced == self.runs {
+            Verdict::Reproducible
+        } else {
+            Verdict::Flaky
+        };
+
+        #[allow(clippy::cast_precision_loss)]
+        let reproducibility = Reproducibility {
+            verdict,
+            runs: self.runs,
+            reproduced,
+            rate: reproduced as f64 / self.runs as f64,
+        };
+        self.last = Some(reproducibility);
+        Ok(true)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        _state: &mut S,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        if let Some(reproducibility) = self.last.take() {
+            testcase.add_metadata(reproducibility);
+        }
+        Ok(())
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.last = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use libafl::{
+    corpus::CorpusId,
+    feedbacks::{MapFeedbackMetadata, NewHashFeedbackMetadata},
+    prelude::Tokens,
+    stages::Stage,
+    state::{HasMetadata, HasNamedMetadata, HasRand, UsesState},
+    Error,
+};
+use libafl_bolts::rands::StdRand;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    solutions::SolutionCounts,
+    sync::SyncProgress,
+    uniqueness::{self, CoverageHashes, StackBuckets},
+};
+
+//スナップショットを書き出す間隔
+const SAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+//再開時に引き継ぐstateの一部
+//queueはInMemoryOnDiskCorpusとして出力ディレクトリに残るので、ここには含めない
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    rand: StdRand,
+    tokens: Option<Tokens>,
+    //MaxMapFeedbackがこれまでに見たカバレッジ
+    history: Option<MapFeedbackMetadata<u8>>,
+    //UniquenessFeedbackがこれまでのsolutionと比べるもので、ないと再開後に同じBugをもう一度保存する
+    crash_history: Option<MapFeedbackMetadata<u8>>,
+    hang_history: Option<MapFeedbackMetadata<u8>>,
+    coverage_hashes: Option<CoverageHashes>,
+    stack_hashes: Option<NewHashFeedbackMetadata>,
+    //再開後もバケットへの重複したヒットを、前回のsolutionの.<名前>.hitsに記録する
+    stack_buckets: Option<StackBuckets>,
+    //fuzzer_statsのsaved_crashesなどを、0から数え直さない
+    counts: Option<SolutionCounts>,
+    //再開後にピアのqueueを最初から取り込み直さない
+    sync: Option<SyncProgress>,
+}
+
+impl Snapshot {
+    fn capture<S>(state: &S, map_name: &str) -> Self
+    where
+        S: HasRand<Rand = StdRand> + HasMetadata + HasNamedMetadata,
+    {
+        Self {
+            rand: *state.rand(),
+            tokens: state.metadata_map().get::<Tokens>().cloned(),
+            history: state
+                .named_metadata_map()
+                .get::<MapFeedbackMetadata<u8>>(map_name)
+                .cloned(),
+            crash_history: state
+                .named_metadata_map()
+                .get::<MapFeedbackMetadata<u8>>(uniqueness::CRASH_HISTORY_NAME)
+                .cloned(),
+            hang_history: state
+                .named_metadata_map()
+                .get::<MapFeedbackMetadata<u8>>(uniqueness::HANG_HISTORY_NAME)
+                .cloned(),
+            coverage_hashes: state.metadata_map().get::<CoverageHashes>().cloned(),
+            stack_hashes: state
+                .named_metadata_map()
+                .get::<NewHashFeedbackMetadata>(uniqueness::STACK_HISTORY_NAME)
+                .cloned(),
+            stack_buckets: state.metadata_map().get::<StackBuckets>().cloned(),
+            counts: state.metadata_map().get::<SolutionCounts>().cloned(),
+            sync: state.metadata_map().get::<SyncProgress>().cloned(),
+        }
+    }
+
+    //スナップショットがなければ、新しいキャンペーンとして扱う
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let bytes = fs::read(path)?;
+        let snapshot = postcard::from_bytes(&bytes).map_err(|err| {
+            Error::illegal_state(format!(
+                "failed to read fuzzer state {}: {err}",
+                path.display()
+            ))
+        })?;
+        Ok(Some(snapshot))
+    }
+
+    //historyを先に戻しておかないと、queueを読み直したときにすべてが新しいカバレッジとして扱われる
+    //solutionのhistoryも同じで、読み直したqueueから前回のクラッシュがもう一度保存されてしまう
+    pub fn restore<S>(self, state: &mut S, map_name: &str)
+    where
+        S: HasRand<Rand = StdRand> + HasMetadata + HasNamedMetadata,
+    {
+        *state.rand_mut() = self.rand;
+
+        if let Some(tokens) = self.tokens {
+            state.add_metadata(tokens);
+        }
+
+        if let Some(history) = self.history {
+            state.add_named_metadata(history, map_name);
+        }
+
+        if let Some(history) = self.crash_history {
+            state.add_named_metadata(history, uniqueness::CRASH_HISTORY_NAME);
+        }
+        if let Some(history) = self.hang_history {
+            state.add_named_metadata(history, uniqueness::HANG_HISTORY_NAME);
+        }
+        if let Some(hashes) = self.coverage_hashes {
+            state.add_metadata(hashes);
+        }
+        if let Some(hashes) = self.stack_hashes {
+            state.add_named_metadata(hashes, uniqueness::STACK_HISTORY_NAME);
+        }
+        if let Some(buckets) = self.stack_buckets {
+            state.add_metadata(buckets);
+        }
+        if let Some(counts) = self.counts {
+            state.add_metadata(counts);
+        }
+        if let Some(progress) = self.sync {
+            state.add_metadata(progress);
+        }
+    }
+
+    //書き込み途中で止まっても前回のスナップショットが壊れないように、renameで置き換える
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let bytes = postcard::to_allocvec(self)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+//一定時間ごとにスナップショットを書き出すステージ
+pub struct SnapshotStage<E, EM, Z> {
+    path: PathBuf,
+    map_name: String,
+    last_save: Instant,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, Z> SnapshotStage<E, EM, Z> {
+    pub fn new(path: PathBuf, map_name: &str) -> Self {
+        Self {
+            path,
+            map_name: map_name.to_string(),
+            last_save: Instant::now(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, EM, Z> UsesState for SnapshotStage<E, EM, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for SnapshotStage<E, EM, Z>
+where
+    E: UsesState,
+    E::State: HasRand<Rand = StdRand> + HasMetadata + HasNamedMetadata,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+        _corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        if self.last_save.elapsed() < SAVE_INTERVAL {
+            return Ok(());
+        }
+
+        Snapshot::capture(state, &self.map_name).save(&self.path)?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use libafl::{
+        corpus::InMemoryCorpus,
+        feedbacks::ConstFeedback,
+        inputs::BytesInput,
+        state::{HasMetadata, HasNamedMetadata, StdState},
+    };
+    use libafl_bolts::rands::StdRand;
+
+    use super::*;
+    use crate::runner::{RunnerState, WorkDir};
+
+    fn new_state() -> RunnerState {
+        StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        )
+        .unwrap()
+    }
+
+    //solutionの重複判定に使うものと数、ピアから取り込んだ位置が、書き出して読み直しても残る
+    #[test]
+    fn snapshot_keeps_solution_state() {
+        let work_dir = WorkDir::new("resume-test").unwrap();
+        let path = work_dir.path().join(".fuzzer_state");
+
+        let mut state = new_state();
+        let mut history = MapFeedbackMetadata::<u8>::new(4);
+        history.history_map[2] = 1;
+        state.add_named_metadata(history, uniqueness::CRASH_HISTORY_NAME);
+        let mut history = MapFeedbackMetadata::<u8>::new(4);
+        history.history_map[3] = 1;
+        state.add_named_metadata(history, uniqueness::HANG_HISTORY_NAME);
+        let mut stacks = NewHashFeedbackMetadata::new();
+        stacks.hash_set.insert(0xdead);
+        state.add_named_metadata(stacks, uniqueness::STACK_HISTORY_NAME);
+        let mut hashes = CoverageHashes::default();
+        hashes.crashes.insert(0xbeef);
+        hashes.hangs.insert(0xcafe);
+        state.add_metadata(hashes);
+        let mut buckets = StackBuckets::default();
+        buckets
+            .solutions
+            .insert(0xdead, "id:000000,sig:06,time:0,execs:1".to_string());
+        state.add_metadata(buckets);
+        state.add_metadata(SolutionCounts {
+            crashes: 3,
+            hangs: 1,
+        });
+        let mut progress = SyncProgress::default();
+        progress.last_times.insert(
+            PathBuf::from("sync/afl/queue"),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+        );
+        state.add_metadata(progress);
+        Snapshot::capture(&state, "mapfeedback_metadata_shmem")
+            .save(&path)
+            .unwrap();
+
+        let mut resumed = new_state();
+        Snapshot::load(&path)
+            .unwrap()
+            .unwrap()
+            .restore(&mut resumed, "mapfeedback_metadata_shmem");
+
+        let history = resumed
+            .named_metadata_map()
+            .get::<MapFeedbackMetadata<u8>>(uniqueness::CRASH_HISTORY_NAME)
+            .unwrap();
+        assert_eq!(history.history_map, vec![0, 0, 1, 0]);
+        let history = resumed
+            .named_metadata_map()
+            .get::<MapFeedbackMetadata<u8>>(uniqueness::HANG_HISTORY_NAME)
+            .unwrap();
+        assert_eq!(history.history_map, vec![0, 0, 0, 1]);
+        let stacks = resumed
+            .named_metadata_map()
+            .get::<NewHashFeedbackMetadata>(uniqueness::STACK_HISTORY_NAME)
+            .unwrap();
+        assert!(stacks.hash_set.contains(&0xdead));
+        let hashes = resumed.metadata::<CoverageHashes>().unwrap();
+        assert!(hashes.crashes.contains(&0xbeef));
+        assert!(hashes.hangs.contains(&0xcafe));
+        assert_eq!(
+            resumed.metadata::<StackBuckets>().unwrap().solutions[&0xdead],
+            "id:000000,sig:06,time:0,execs:1"
+        );
+        let counts = resumed.metadata::<SolutionCounts>().unwrap();
+        assert_eq!((counts.crashes, counts.hangs), (3, 1));
+        let progress = resumed.metadata::<SyncProgress>().unwrap();
+        assert_eq!(
+            progress.last_times[Path::new("sync/afl/queue")],
+            SystemTime::UNIX_EPOCH + Duration::from_secs(60)
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use libafl::{
+    corpus::InMemoryCorpus,
+    events::NopEventManager,
+    executors::{ExitKind, HasObservers},
+    feedbacks::ConstFeedback,
+    inputs::BytesInput,
+    observers::{HitcountsMapObserver, ObserverWithHashField, StdMapObserver, TimeObserver},
+    schedulers::QueueScheduler,
+    state::StdState,
+    Error, StdFuzzer,
+};
+use libafl_bolts::{
+    current_nanos,
+    rands::StdRand,
+    shmem::{ShMem, ShMemProvider, StdShMemProvider},
+    tuples::{tuple_list, MatchName},
+    AsIter,
+};
+
+use crate::{
+    config::ToolConfig,
+    executor::{
+        self, CoverageObserver, Executor, Observers, SignalObserver, SlowObserver, SIGNAL_NAME,
+        SLOW_NAME,
+    },
+    stacktrace::{StackHashObserver, STACK_NAME},
+};
+
+pub type RunnerState =
+    StdState<BytesInput, InMemoryCorpus<BytesInput>, StdRand, InMemoryCorpus<BytesInput>>;
+
+pub type RunnerFuzzer<'a> =
+    StdFuzzer<QueueScheduler<RunnerState>, ConstFeedback, ConstFeedback, Observers<'a>>;
+
+pub const MAP_NAME: &str = "shmem";
+
+//1回の実行で分かったこと
+#[derive(Debug)]
+pub struct Execution {
+    pub exit_kind: ExitKind,
+    pub signal: Option<i32>,
+    pub stack_hash: Option<u64>,
+    pub exec_time: Option<Duration>,
+    //タイムアウトしたが、hang_timeoutで実行し直すと終わったときの、1回目のタイムアウト
+    pub slow: Option<Duration>,
+    //HitcountsMapObserverで分類した後の、0でないエントリの(インデックス, 値)
+    pub coverage: Vec<(usize, u8)>,
+}
+
+//サブコマンド(tmin, cminなど)で、ファジングと同じexecutorに入力を1つずつ流す
+//feedbackとobjectiveは常にfalseなので、コーパスにもsolutionにも何も追加されない
+pub struct Runner<'a> {
+    pub state: RunnerState,
+    pub fuzzer: RunnerFuzzer<'a>,
+    pub executor: Executor<'a, RunnerState>,
+    pub manager: NopEventManager<RunnerState>,
+}
+
+impl<'a> Runner<'a> {
+    //shmemはターゲットとカバレッジを共有するためのもので、Runnerより長く生きている必要がある
+    //大きさはcoverage_shmemで、ターゲットに合わせておく
+    pub fn new<SHM>(
+        config: &ToolConfig,
+        shmem: &'a mut SHM,
+        work_dir: &WorkDir,
+    ) -> Result<Self, Error>
+    where
+        SHM: ShMem,
+    {
+        let map_size = shmem.len();
+        shmem.write_to_env("__AFL_SHM_ID")?;
+        let map_observer = HitcountsMapObserver::new(unsafe {
+            StdMapObserver::new(MAP_NAME, shmem.as_mut_slice())
+        });
+
+        let stack_observer = StackHashObserver::new(
+            STACK_NAME,
+            work_dir.path().join(".asan"),
+            config.objective.stack_frames,
+        )?;
+
+        let mut feedback = ConstFeedback::new(false);
+        let mut objective = ConstFeedback::new(false);
+
+        let state = StdState::new(
+            StdRand::with_seed(current_nanos()),
+            InMemoryCorpus::new(),
+            InMemoryCorpus::new(),
+            &mut feedback,
+            &mut objective,
+        )?;
+        let fuzzer = StdFuzzer::new(QueueScheduler::new(), feedback, objective);
+
+        let executor = {
+            let cur_input = work_dir.path().join(".cur_input");
+            let asan_log = stack_observer.log_path();
+            let observers = tuple_list!(
+                map_observer,
+                TimeObserver::new("time"),
+                stack_observer,
+                SignalObserver::new(SIGNAL_NAME),
+                SlowObserver::new(SLOW_NAME)
+            );
+            executor::build(
+                &config.target,
+                &config.target.program,
+                &cur_input,
+                Some(&asan_log),
+                map_size,
+                observers,
+            )?
+        };
+
+        Ok(Self {
+            state,
+            fuzzer,
+            executor,
+            manager: NopEventManager::new(),
+        })
+    }
+
+    pub fn run(&mut self, input: &BytesInput) -> Result<Execution, Error> {
+        let exit_kind = self.fuzzer.execute_input(
+            &mut self.state,
+            &mut self.executor,
+            &mut self.manager,
+            input,
+        )?;
+
+        let observers = self.executor.observers();
+        let coverage = observers
+            .match_name::<CoverageObserver>(MAP_NAME)
+            .ok_or_else(|| Error::key_not_found("coverage map observer not found"))?
+            .as_iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, hits)| *hits != 0)
+            .collect();
+
+        Ok(Execution {
+            exit_kind,
+            signal: observers
+                .match_name::<SignalObserver>(SIGNAL_NAME)
+                .and_then(SignalObserver::signal),
+            stack_hash: stack_hash(observers),
+            exec_time: observers
+                .match_name::<TimeObserver>("time")
+                .and_then(|observer| *observer.last_runtime()),
+            slow: observers
+                .match_name::<SlowObserver>(SLOW_NAME)
+                .and_then(SlowObserver::timeout),
+            coverage,
+        })
+    }
+}
+
+//ターゲットが報告した大きさで、カバレッジの共有メモリを作る
+pub fn coverage_shmem(
+    config: &ToolConfig,
+    work_dir: &WorkDir,
+) -> Result<<StdShMemProvider as ShMemProvider>::ShMem, Error> {
+    let cur_input = work_dir.path().join(".cur_input");
+    let map_size = executor::map_size(&config.target, &config.target.program, &cur_input)?;
+    StdShMemProvider::new()?.new_shmem(map_size)
+}
+
+pub fn stack_hash<OT>(observers: &OT) -> Option<u64>
+where
+    OT: MatchName,
+{
+    observers
+        .match_name::<StackHashObserver>(STACK_NAME)
+        .and_then(ObserverWithHashField::hash)
+}
+
+//cur_inputとASanのログを置く一時ディレクトリで、dropしたときに消す
+//出力先のディレクトリを汚さないように、サブコマンドではこちらを使う
+pub struct WorkDir(PathBuf);
+
+impl WorkDir {
+    pub fn new(name: &str) -> Result<Self, Error> {
+        let path = env::temp_dir().join(format!("libafl-{name}-{}", process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+//ファイルならそれだけを、ディレクトリなら直下のファイルを名前順に返す
+//ドットファイルはlibaflのload_initial_inputsと同じく無視する
+pub fn input_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,111 @@
+use libafl::{
+    corpus::{CorpusId, HasTestcase},
+    inputs::UsesInput,
+    observers::{MapObserver, ObserversTuple},
+    schedulers::{
+        powersched::PowerSchedule, QueueScheduler, RandScheduler, Scheduler, StdWeightedScheduler,
+    },
+    state::{HasCorpus, HasMetadata, HasRand, UsesState},
+    Error,
+};
+
+use crate::config::{FuzzerConfig, PowerScheduleKind, SchedulerKind};
+
+//設定でスケジューラを切り替えられるように、列挙型でまとめる
+//StdFuzzerの型が変わらないので、どれを選んでも同じコードで組み立てられる
+//Weightedはmap observerの名前を覚えて、実行ごとの経路のハッシュから選ばれた回数を数える
+#[derive(Debug, Clone)]
+pub enum BaseScheduler<O, S> {
+    Queue(QueueScheduler<S>),
+    Random(RandScheduler<S>),
+    Weighted(StdWeightedScheduler<O, S>),
+}
+
+impl<O, S> BaseScheduler<O, S>
+where
+    O: MapObserver,
+    S: HasCorpus + HasMetadata + HasRand,
+{
+    //power scheduleのメタデータはstateに保存されるので、stateを受け取る
+    pub fn new(config: &FuzzerConfig, state: &mut S, map_observer: &O) -> Self {
+        match config.scheduler {
+            SchedulerKind::Queue => Self::Queue(QueueScheduler::new()),
+            SchedulerKind::Random => Self::Random(RandScheduler::new()),
+            SchedulerKind::Weighted => Self::Weighted(StdWeightedScheduler::with_schedule(
+                state,
+                map_observer,
+                Some(config.schedule.into()),
+            )),
+        }
+    }
+}
+
+impl From<PowerScheduleKind> for PowerSchedule {
+    fn from(kind: PowerScheduleKind) -> Self {
+        match kind {
+            PowerScheduleKind::Explore => Self::EXPLORE,
+            PowerScheduleKind::Fast => Self::FAST,
+            PowerScheduleKind::Coe => Self::COE,
+            PowerScheduleKind::Lin => Self::LIN,
+            PowerScheduleKind::Quad => Self::QUAD,
+            PowerScheduleKind::Exploit => Self::EXPLOIT,
+        }
+    }
+}
+
+impl<O, S> UsesState for BaseScheduler<O, S>
+where
+    S: UsesInput + HasTestcase,
+{
+    type State = S;
+}
+
+impl<O, S> Scheduler for BaseScheduler<O, S>
+where
+    O: MapObserver,
+    S: HasCorpus + HasMetadata + HasRand + HasTestcase,
+{
+    fn on_add(&mut self, state: &mut S, idx: CorpusId) -> Result<(), Error> {
+        match self {
+            Self::Queue(scheduler) => scheduler.on_add(state, idx),
+            Self::Random(scheduler) => scheduler.on_add(state, idx),
+            Self::Weighted(scheduler) => scheduler.on_add(state, idx),
+        }
+    }
+
+    fn on_evaluation<OT>(
+        &mut self,
+        state: &mut S,
+        input: &S::Input,
+        observers: &OT,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        match self {
+            Self::Queue(scheduler) => scheduler.on_evaluation(state, input, observers),
+            Self::Random(scheduler) => scheduler.on_evaluation(state, input, observers),
+            Self::Weighted(scheduler) => scheduler.on_evaluation(state, input, observers),
+        }
+    }
+
+    fn next(&mut self, state: &mut S) -> Result<CorpusId, Error> {
+        match self {
+            Self::Queue(scheduler) => scheduler.next(state),
+            Self::Random(scheduler) => scheduler.next(state),
+            Self::Weighted(scheduler) => scheduler.next(state),
+        }
+    }
+
+    fn set_current_scheduled(
+        &mut self,
+        state: &mut S,
+        next_idx: Option<CorpusId>,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Queue(scheduler) => scheduler.set_current_scheduled(state, next_idx),
+            Self::Random(scheduler) => scheduler.set_current_scheduled(state, next_idx),
+            Self::Weighted(scheduler) => scheduler.set_current_scheduled(state, next_idx),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{collections::BTreeMap, fmt::Write as _, fs, path::Path};
+
+use libafl::{
+    inputs::{BytesInput, Input},
+    Error,
+};
+
+use crate::{
+    cli::ShowmapOptions,
+    config::ToolConfig,
+    runner::{self, Runner, WorkDir},
+};
+
+//afl-showmapと同じく、0でないエントリを1行に1つ"index:value"で書き出す
+//値はHitcountsMapObserverが分類したバケットを、afl-showmapと同じ1..8のクラスに直したもの
+pub fn showmap(config: &ToolConfig, options: &ShowmapOptions) -> Result<(), Error> {
+    let is_dir = options.input.is_dir();
+    let files = runner::input_files(&options.input)?;
+
+    let work_dir = WorkDir::new("showmap")?;
+    let mut shmem = runner::coverage_shmem(config, &work_dir)?;
+    let mut runner = Runner::new(config, &mut shmem, &work_dir)?;
+
+    if is_dir {
+        fs::create_dir_all(&options.output)?;
+    }
+
+    //バケットは1ビットずつの値なので、ORを取ればそのエントリで見たバケットの集合になる
+    //書き出すときは、その中で最も大きいクラスにする
+    let mut union = BTreeMap::new();
+
+    for path in &files {
+        let input = BytesInput::from_file(path)?;
+        let execution = runner.run(&input)?;
+
+        let output = match path.file_name() {
+            Some(name) if is_dir => options.output.join(name),
+            _ => options.output.clone(),
+        };
+        write_map(&output, execution.coverage.iter().copied())?;
+
+        for (index, bucket) in execution.coverage {
+            *union.entry(index).or_insert(0) |= bucket;
+        }
+    }
+
+    if let Some(path) = &options.union {
+        write_map(path, union.iter().map(|(index, bucket)| (*index, *bucket)))?;
+    }
+
+    println!(
+        "wrote maps of {} inputs to {}, {} entries in total",
+        files.len(),
+        options.output.display(),
+        union.len()
+    );
+    Ok(())
+}
+
+fn write_map(path: &Path, entries: impl Iterator<Item = (usize, u8)>) -> Result<(), Error> {
+    let mut text = String::new();
+    for (index, bucket) in entries {
+        writeln!(text, "{index:06}:{}", afl_class(bucket)).unwrap();
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+//HitcountsMapObserverはヒット回数を1,2,4,...,128のどれか1ビットに分類し、
+//afl-showmapはその分類を1..8の番号で書くので、最上位のビットの位置に直す
+fn afl_class(bucket: u8) -> u8 {
+    (u8::BITS - bucket.leading_zeros()) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //ヒット回数1, 2, 3, 4-7, 8-15, 16-31, 32-127, 128-のバケットが、afl-showmapのクラス1..8になる
+    #[test]
+    fn buckets_map_to_afl_classes() {
+        let buckets = [1, 2, 4, 8, 16, 32, 64, 128];
+        let classes = buckets.map(afl_class);
+        assert_eq!(classes, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    //unionで複数のバケットを見たエントリは、最も大きいクラスになる
+    #[test]
+    fn union_uses_highest_class() {
+        assert_eq!(afl_class(1 | 4), 3);
+        assert_eq!(afl_class(2 | 128), 8);
+    }
+}
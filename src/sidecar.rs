@@ -0,0 +1,228 @@
+use std::{
+    fs, io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::{HasTargetBytes, Input, UsesInput},
+    observers::{ObserversTuple, TimeObserver},
+    state::{HasClientPerfMonitor, HasMetadata, HasStartTime},
+    Error,
+};
+use libafl_bolts::{impl_serdeany, AsSlice, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    afl::Origin,
+    config::TargetConfig,
+    executor::{SignalObserver, SIGNAL_NAME},
+    mutators::AppliedMutations,
+    reproduce::Reproducibility,
+    solutions::SolutionKind,
+    timeout::AutoTimeout,
+    uniqueness::UniquenessMetadata,
+};
+
+//見つけたときの状況で、solutionのTestcaseに付ける
+//SolutionCorpusが保存するときに、入力の隣の.<名前>.jsonに書き出す
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SolutionInfo {
+    //UNIXエポックからの秒数
+    pub discovered_at: u64,
+    pub executions: u64,
+    pub signal: Option<i32>,
+    pub exec_time_us: Option<u64>,
+    //mutationの元にしたコーパスのid
+    pub parent_id: Option<usize>,
+    pub mutations: Vec<String>,
+    pub target: TargetInfo,
+}
+
+impl_serdeany!(SolutionInfo);
+
+//実行したときのexecutorの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetInfo {
+    pub program: String,
+    pub args: Vec<String>,
+    //timeout_per_kbで、入力の長さの分を足したもの
+    pub timeout_ms: u64,
+    pub hang_timeout_ms: Option<u64>,
+}
+
+//サイドカーに書き出す内容で、SolutionInfoに種類と再現性、ユニークと判断した基準を加える
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    kind: SolutionKind,
+    #[serde(flatten)]
+    info: &'a SolutionInfo,
+    reproducibility: Option<&'a Reproducibility>,
+    uniqueness: Option<&'a UniquenessMetadata>,
+}
+
+//SolutionInfoを付けたTestcaseだけ、サイドカーを書き出す
+//Testcaseは保存済みで、ファイルのパスが決まっていること
+pub fn write<I: Input>(testcase: &Testcase<I>) -> Result<(), Error> {
+    let Some(info) = testcase.metadata_map().get::<SolutionInfo>() else {
+        return Ok(());
+    };
+    let Some(path) = path(testcase) else {
+        return Ok(());
+    };
+
+    let sidecar = Sidecar {
+        kind: testcase
+            .metadata_map()
+            .get::<SolutionKind>()
+            .copied()
+            .unwrap_or(SolutionKind::Crash),
+        info,
+        reproducibility: testcase.metadata_map().get::<Reproducibility>(),
+        uniqueness: testcase.metadata_map().get::<UniquenessMetadata>(),
+    };
+    fs::write(path, serde_json::to_vec_pretty(&sidecar)?)?;
+    Ok(())
+}
+
+pub fn remove<I: Input>(testcase: &Testcase<I>) -> Result<(), Error> {
+    let Some(path) = path(testcase) else {
+        return Ok(());
+    };
+
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+//OnDiskCorpusの.<名前>.metadataと同じく隠しファイルにして、replayなどで入力として読まれないようにする
+fn path<I: Input>(testcase: &Testcase<I>) -> Option<PathBuf> {
+    let file_path = testcase.file_path().as_ref()?;
+    let filename = testcase.filename().as_ref()?;
+    Some(file_path.with_file_name(format!(".{filename}.json")))
+}
+
+//objectiveの最後にfeedback_and_fast!でつなぎ、solutionになる実行だけにSolutionInfoを付ける
+//条件判定には寄与しないので、常にtrueを返す
+#[derive(Debug)]
+pub struct SolutionInfoFeedback {
+    //mutationの並びと一致させる
+    names: Vec<String>,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    timeout_per_kb: Duration,
+    hang_timeout: Option<Duration>,
+}
+
+impl SolutionInfoFeedback {
+    pub fn new(config: &TargetConfig, names: Vec<String>) -> Self {
+        Self {
+            names,
+            program: config.program.to_string_lossy().into_owned(),
+            args: config
+                .args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            timeout: config.timeout.initial(),
+            timeout_per_kb: config.timeout_per_kb,
+            hang_timeout: config.hang_timeout,
+        }
+    }
+}
+
+impl Named for SolutionInfoFeedback {
+    fn name(&self) -> &str {
+        "SolutionInfoFeedback"
+    }
+}
+
+impl<S> Feedback<S> for SolutionInfoFeedback
+where
+    S: UsesInput + HasClientPerfMonitor + HasMetadata + HasStartTime,
+    S::Input: HasTargetBytes,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(true)
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let discovered_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+
+        let mutations = state
+            .metadata_map()
+            .get::<AppliedMutations>()
+            .map(|applied| {
+                applied
+                    .ids
+                    .iter()
+                    .filter_map(|id| self.names.get(*id).cloned())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        //autoのときは、シードから求めた値を使う
+        let timeout = state
+            .metadata_map()
+            .get::<AutoTimeout>()
+            .map_or(self.timeout, |auto| auto.timeout);
+        let len = testcase
+            .input()
+            .as_ref()
+            .map_or(0, |input| input.target_bytes().as_slice().len());
+        let timeout = timeout + self.timeout_per_kb.mul_f64(len as f64 / 1024.0);
+
+        let info = SolutionInfo {
+            discovered_at,
+            executions: *testcase.executions() as u64,
+            signal: observers
+                .match_name::<SignalObserver>(SIGNAL_NAME)
+                .and_then(SignalObserver::signal),
+            exec_time_us: observers
+                .match_name::<TimeObserver>("time")
+                .and_then(|observer| *observer.last_runtime())
+                .map(|time| time.as_micros() as u64),
+            parent_id: testcase.parent_id().map(usize::from),
+            mutations,
+            target: TargetInfo {
+                program: self.program.clone(),
+                args: self.args.clone(),
+                timeout_ms: timeout.as_millis() as u64,
+                hang_timeout_ms: self.hang_timeout.map(|timeout| timeout.as_millis() as u64),
+            },
+        };
+        testcase.add_metadata(info);
+
+        //AFL++の名前は、queueと同じくOriginから作る
+        testcase.add_metadata(Origin::capture(state, observers));
+        Ok(())
+    }
+}
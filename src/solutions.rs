@@ -0,0 +1,357 @@
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::BTreeMap,
+    marker::PhantomData,
+    ops::Bound,
+    path::Path,
+};
+
+use libafl::{
+    corpus::{Corpus, CorpusId, HasTestcase, OnDiskCorpus, Testcase},
+    events::{Event, EventFirer},
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::{Input, UsesInput},
+    monitors::UserStats,
+    observers::ObserversTuple,
+    state::{HasClientPerfMonitor, HasMetadata},
+    Error,
+};
+use libafl_bolts::{impl_serdeany, Named};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{afl::AflCorpus, sidecar};
+
+//Bugの種類で、solutionのTestcaseのメタデータとして付与される
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SolutionKind {
+    Crash,
+    Hang,
+}
+
+impl_serdeany!(SolutionKind);
+
+impl SolutionKind {
+    pub fn from_exit_kind(exit_kind: &ExitKind) -> Option<Self> {
+        match exit_kind {
+            ExitKind::Crash | ExitKind::Oom => Some(Self::Crash),
+            ExitKind::Timeout => Some(Self::Hang),
+            _ => None,
+        }
+    }
+
+    //monitorに送る数の名前
+    pub fn stat_name(self) -> &'static str {
+        match self {
+            Self::Crash => "crashes",
+            Self::Hang => "hangs",
+        }
+    }
+}
+
+//種類ごとに見つけたsolutionの数
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SolutionCounts {
+    pub crashes: u64,
+    pub hangs: u64,
+}
+
+impl_serdeany!(SolutionCounts);
+
+impl SolutionCounts {
+    pub fn count_mut(&mut self, kind: SolutionKind) -> &mut u64 {
+        match kind {
+            SolutionKind::Crash => &mut self.crashes,
+            SolutionKind::Hang => &mut self.hangs,
+        }
+    }
+}
+
+//objectiveの最後にfeedback_and_fast!でつなぎ、solutionになる実行だけを見る
+//exit kindからBugの種類を決めてTestcaseに付与し、種類ごとの数をmonitorに送る
+#[derive(Debug, Default)]
+pub struct SolutionKindFeedback {
+    last: Option<SolutionKind>,
+}
+
+impl SolutionKindFeedback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Named for SolutionKindFeedback {
+    fn name(&self) -> &str {
+        "SolutionKindFeedback"
+    }
+}
+
+impl<S> Feedback<S> for SolutionKindFeedback
+where
+    S: UsesInput + HasClientPerfMonitor + HasMetadata,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        if !state.has_metadata::<SolutionCounts>() {
+            state.add_metadata(SolutionCounts::default());
+        }
+        Ok(())
+    }
+
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        let Some(kind) = SolutionKind::from_exit_kind(exit_kind) else {
+            return Ok(false);
+        };
+        self.last = Some(kind);
+
+        let count = state.metadata_mut::<SolutionCounts>()?.count_mut(kind);
+        *count += 1;
+        let value = *count;
+
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: kind.stat_name().to_string(),
+                value: UserStats::Number(value),
+                phantom: PhantomData,
+            },
+        )?;
+
+        Ok(true)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        _state: &mut S,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        if let Some(kind) = self.last.take() {
+            testcase.add_metadata(kind);
+        }
+        Ok(())
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.last = None;
+        Ok(())
+    }
+}
+
+//コーパスのfeedbackの先頭にfeedback_and_fast!でつなぎ、正常に終了した実行だけをqueueの候補にする
+//uniquenessで重複として捨てたクラッシュやハングが、新しいエッジを理由にqueueに入らないようにする
+#[derive(Debug, Default)]
+pub struct ExitOkFeedback;
+
+impl ExitOkFeedback {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for ExitOkFeedback {
+    fn name(&self) -> &str {
+        "ExitOkFeedback"
+    }
+}
+
+impl<S> Feedback<S> for ExitOkFeedback
+where
+    S: UsesInput + HasClientPerfMonitor,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(*exit_kind == ExitKind::Ok)
+    }
+}
+
+//SolutionKindに応じて、crashes/とhangs/に振り分けて保存するcorpus
+//SolutionInfoが付いていれば、入力の隣にJSONのサイドカーも書き出す
+//外から見えるCorpusIdは、両方を通した通し番号になる
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "I: DeserializeOwned")]
+pub struct SolutionCorpus<I>
+where
+    I: Input,
+{
+    crashes: AflCorpus<OnDiskCorpus<I>>,
+    hangs: AflCorpus<OnDiskCorpus<I>>,
+    entries: BTreeMap<CorpusId, (SolutionKind, CorpusId)>,
+    next_id: usize,
+    current: Option<CorpusId>,
+}
+
+impl<I> SolutionCorpus<I>
+where
+    I: Input,
+{
+    pub fn new(output: &Path) -> Result<Self, Error> {
+        let crashes = output.join("crashes");
+        let hangs = output.join("hangs");
+        Ok(Self {
+            crashes: AflCorpus::new(OnDiskCorpus::new(&crashes)?, &crashes, true)?,
+            hangs: AflCorpus::new(OnDiskCorpus::new(&hangs)?, &hangs, false)?,
+            entries: BTreeMap::new(),
+            next_id: 0,
+            current: None,
+        })
+    }
+
+    fn inner(&self, kind: SolutionKind) -> &AflCorpus<OnDiskCorpus<I>> {
+        match kind {
+            SolutionKind::Crash => &self.crashes,
+            SolutionKind::Hang => &self.hangs,
+        }
+    }
+
+    fn inner_mut(&mut self, kind: SolutionKind) -> &mut AflCorpus<OnDiskCorpus<I>> {
+        match kind {
+            SolutionKind::Crash => &mut self.crashes,
+            SolutionKind::Hang => &mut self.hangs,
+        }
+    }
+
+    fn entry(&self, id: CorpusId) -> Result<(SolutionKind, CorpusId), Error> {
+        self.entries
+            .get(&id)
+            .copied()
+            .ok_or_else(|| Error::key_not_found(format!("Index {id} not found")))
+    }
+
+    //種類のメタデータがないものは、クラッシュとして扱う
+    fn kind_of(testcase: &Testcase<I>) -> SolutionKind {
+        testcase
+            .metadata_map()
+            .get::<SolutionKind>()
+            .copied()
+            .unwrap_or(SolutionKind::Crash)
+    }
+}
+
+impl<I> UsesInput for SolutionCorpus<I>
+where
+    I: Input,
+{
+    type Input = I;
+}
+
+impl<I> Corpus for SolutionCorpus<I>
+where
+    I: Input,
+{
+    fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn add(&mut self, testcase: Testcase<I>) -> Result<CorpusId, Error> {
+        let kind = Self::kind_of(&testcase);
+        let inner_id = self.inner_mut(kind).add(testcase)?;
+        sidecar::write(&self.inner(kind).get(inner_id)?.borrow())?;
+
+        let id = CorpusId::from(self.next_id);
+        self.next_id += 1;
+        self.entries.insert(id, (kind, inner_id));
+        Ok(id)
+    }
+
+    fn replace(&mut self, id: CorpusId, testcase: Testcase<I>) -> Result<Testcase<I>, Error> {
+        let (kind, inner_id) = self.entry(id)?;
+        let old = self.inner_mut(kind).replace(inner_id, testcase)?;
+        sidecar::write(&self.inner(kind).get(inner_id)?.borrow())?;
+        Ok(old)
+    }
+
+    fn remove(&mut self, id: CorpusId) -> Result<Testcase<I>, Error> {
+        let (kind, inner_id) = self.entry(id)?;
+        let testcase = self.inner_mut(kind).remove(inner_id)?;
+        sidecar::remove(&testcase)?;
+        self.entries.remove(&id);
+        Ok(testcase)
+    }
+
+    fn get(&self, id: CorpusId) -> Result<&RefCell<Testcase<I>>, Error> {
+        let (kind, inner_id) = self.entry(id)?;
+        self.inner(kind).get(inner_id)
+    }
+
+    fn current(&self) -> &Option<CorpusId> {
+        &self.current
+    }
+
+    fn current_mut(&mut self) -> &mut Option<CorpusId> {
+        &mut self.current
+    }
+
+    fn next(&self, id: CorpusId) -> Option<CorpusId> {
+        self.entries
+            .range((Bound::Excluded(id), Bound::Unbounded))
+            .next()
+            .map(|(id, _)| *id)
+    }
+
+    fn prev(&self, id: CorpusId) -> Option<CorpusId> {
+        self.entries.range(..id).next_back().map(|(id, _)| *id)
+    }
+
+    fn first(&self) -> Option<CorpusId> {
+        self.entries.keys().next().copied()
+    }
+
+    fn last(&self) -> Option<CorpusId> {
+        self.entries.keys().next_back().copied()
+    }
+
+    fn nth(&self, nth: usize) -> CorpusId {
+        *self
+            .entries
+            .keys()
+            .nth(nth)
+            .unwrap_or_else(|| panic!("Failed to get the {nth} CorpusId"))
+    }
+
+    fn load_input_into(&self, testcase: &mut Testcase<I>) -> Result<(), Error> {
+        let kind = Self::kind_of(testcase);
+        self.inner(kind).load_input_into(testcase)
+    }
+
+    fn store_input_from(&self, testcase: &Testcase<I>) -> Result<(), Error> {
+        let kind = Self::kind_of(testcase);
+        self.inner(kind).store_input_from(testcase)
+    }
+}
+
+impl<I> HasTestcase for SolutionCorpus<I>
+where
+    I: Input,
+{
+    fn testcase(&self, id: CorpusId) -> Result<Ref<'_, Testcase<I>>, Error> {
+        Ok(self.get(id)?.borrow())
+    }
+
+    fn testcase_mut(&self, id: CorpusId) -> Result<RefMut<'_, Testcase<I>>, Error> {
+        Ok(self.get(id)?.borrow_mut())
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    executors::ExitKind,
+    inputs::UsesInput,
+    observers::{Observer, ObserverWithHashField},
+    Error,
+};
+use libafl_bolts::{hash_std, Named};
+use serde::{Deserialize, Serialize};
+
+//ASanのレポートは、ASAN_OPTIONSのlog_pathに<log_path>.<pid>として書き込まれる
+const LOG_PREFIX: &str = "asan";
+
+pub const STACK_NAME: &str = "stacktrace";
+
+//クラッシュの場所ではなく、ランタイムの中のフレーム
+//abortやASanのレポート関数はどのクラッシュでも同じなので、ハッシュに含めない
+//シンボル化されていれば関数名、されていなければモジュール名で判断する
+const RUNTIME_FRAMES: &[&str] = &[
+    "in __asan",
+    "in __sanitizer",
+    "in __interceptor",
+    "in __pthread_kill",
+    "in __libc_start",
+    "in raise ",
+    "in abort ",
+    "libc.so",
+    "libasan.so",
+    "libclang_rt.",
+    "libstdc++.so",
+    "libgcc_s.so",
+];
+
+//ASanのスタックトレースの上からN個のフレームで、クラッシュのハッシュを計算する
+//libaflのAsanBacktraceObserverは全フレームのアドレスのXORなので、
+//ASLRでforkserverごとにアドレスが変わると、同じバグが別のハッシュになってしまう
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StackHashObserver {
+    name: String,
+    log_dir: PathBuf,
+    frames: usize,
+    //TargetExecutorが書き込む、直前に実行した子プロセスのpid
+    pid: Option<i32>,
+    hash: Option<u64>,
+}
+
+impl StackHashObserver {
+    pub fn new(name: &str, log_dir: PathBuf, frames: usize) -> Result<Self, Error> {
+        fs::create_dir_all(&log_dir)?;
+        Ok(Self {
+            name: name.to_string(),
+            log_dir,
+            frames,
+            pid: None,
+            hash: None,
+        })
+    }
+
+    pub fn set_pid(&mut self, pid: i32) {
+        self.pid = Some(pid);
+    }
+
+    //ターゲットのASAN_OPTIONSに渡すlog_path
+    pub fn log_path(&self) -> PathBuf {
+        self.log_dir.join(LOG_PREFIX)
+    }
+
+    //直前の子プロセスのレポートだけを読み、ディレクトリは終了の仕方によらず空にする
+    //タイムアウトや正常終了で残ったレポートや、ターゲットがforkしたプロセスのものを、次のクラッシュで読まない
+    fn take_report(&self) -> Result<Option<String>, Error> {
+        let current = self.pid.map(|pid| format!("{LOG_PREFIX}.{pid}"));
+        let mut report = None;
+
+        for entry in fs::read_dir(&self.log_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if current
+                .as_deref()
+                .is_some_and(|current| entry.file_name() == current)
+            {
+                report = Some(fs::read_to_string(&path)?);
+            }
+            fs::remove_file(&path)?;
+        }
+
+        Ok(report)
+    }
+}
+
+//"    #3 0x55d0c1 in main /src/target.c:27:39"のような行から、アドレスを除いた部分を取り出す
+//最初のスタックトレース(クラッシュした箇所)だけを見る
+//ハッシュは再開後やビルドし直した後も同じバケットになるように、Rustのバージョンで変わらないxxh3で求める
+fn stack_hash(report: &str, frames: usize) -> Option<u64> {
+    let mut stack = String::new();
+    let mut count = 0;
+    let mut in_stack = false;
+
+    for line in report.lines().map(str::trim) {
+        let Some(frame) = line.strip_prefix('#') else {
+            if in_stack {
+                break;
+            }
+            continue;
+        };
+        in_stack = true;
+
+        let location = frame
+            .split_whitespace()
+            .skip(2)
+            .collect::<Vec<_>>()
+            .join(" ");
+        if RUNTIME_FRAMES
+            .iter()
+            .any(|runtime| location.contains(runtime))
+        {
+            continue;
+        }
+
+        stack.push_str(&location);
+        stack.push('\n');
+        count += 1;
+        if count == frames {
+            break;
+        }
+    }
+
+    (count > 0).then(|| hash_std(stack.as_bytes()))
+}
+
+impl Named for StackHashObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ObserverWithHashField for StackHashObserver {
+    fn hash(&self) -> Option<u64> {
+        self.hash
+    }
+}
+
+impl<S> Observer<S> for StackHashObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.pid = None;
+        self.hash = None;
+        Ok(())
+    }
+
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &S::Input,
+        exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        let report = self.take_report()?;
+        if *exit_kind == ExitKind::Crash {
+            self.hash = report.and_then(|report| stack_hash(&report, self.frames));
+        }
+        Ok(())
+    }
+}
+
+//バケットに重複してヒットしたときの実行回数を、.<solution>.hitsに1行ずつ追記する
+//隠しファイルにして、crashes/の中でsolutionとして読まれないようにする
+pub fn record_hit(path: &Path, executions: usize) -> Result<(), Error> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{executions}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::{
+        corpus::InMemoryCorpus, feedbacks::ConstFeedback, inputs::BytesInput, state::StdState,
+    };
+    use libafl_bolts::rands::StdRand;
+
+    use super::*;
+    use crate::runner::{RunnerState, WorkDir};
+
+    //シンボル化されたレポートで、ASLRでアドレスだけが実行ごとに変わる
+    const SYMBOLIZED: &str = "\
+=================================================================
+==4242==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000015
+READ of size 1 at 0x602000000015 thread T0
+    #0 0x7f3a1c2b1d1c in __asan_memcpy (/usr/lib/x86_64-linux-gnu/libasan.so.8+0xbd1c)
+    #1 0x55d0c1a2b3c4 in parse_header /src/target.c:12:5
+    #2 0x55d0c1a2b5d6 in parse /src/target.c:20:9
+    #3 0x55d0c1a2b7e8 in main /src/target.c:27:39
+    #4 0x7f3a1c0a1d8f in __libc_start_call_main ../sysdeps/nptl/libc_start_call_main.h:58:16
+
+0x602000000015 is located 0 bytes after 5-byte region [0x602000000010,0x602000000015)
+allocated by thread T0 here:
+    #0 0x7f3a1c2b3f57 in malloc (/usr/lib/x86_64-linux-gnu/libasan.so.8+0xdbf57)
+    #1 0x55d0c1a2b9fa in main /src/target.c:25:17
+";
+
+    //abort_on_errorで、シグナルを送るまでのフレームが上に積まれている
+    const ABORTED: &str = "\
+==4243==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000015
+    #0 0x7f9e0a0b2a7c in __pthread_kill_implementation nptl/pthread_kill.c:44:76
+    #1 0x7f9e0a05e476 in raise ../sysdeps/posix/raise.c:26:13
+    #2 0x7f9e0a0447f3 in abort stdlib/abort.c:79:7
+    #3 0x7f9e0a4c1d1c in __sanitizer::Abort() (/usr/lib/x86_64-linux-gnu/libasan.so.8+0xbd1c)
+    #4 0x55a7e3f1b3c4 in parse_header /src/target.c:12:5
+    #5 0x55a7e3f1b5d6 in parse /src/target.c:20:9
+    #6 0x55a7e3f1b7e8 in main /src/target.c:27:39
+";
+
+    //symbolize=0のレポートで、モジュール内のオフセットはASLRでも変わらない
+    const UNSYMBOLIZED: &str = "\
+==4244==ERROR: AddressSanitizer: SEGV on unknown address 0x000000000000
+    #0 0x7f3a1c0c5d3e  (/lib/x86_64-linux-gnu/libc.so.6+0x18bd3e)
+    #1 0x55d0c1a2b3c4  (/work/target+0x1234)
+    #2 0x55d0c1a2b5d6  (/work/target+0x1456)
+    #3 0x7f3a1c0a1d8f  (/lib/x86_64-linux-gnu/libc.so.6+0x29d8f)
+";
+
+    //ターゲットのフレームのアドレスを、ASLRで別の場所に読み込まれた実行のものにする
+    fn relocate(report: &str, from: &str, to: &str) -> String {
+        let relocated = report.replace(from, to);
+        assert_ne!(relocated, report);
+        relocated
+    }
+
+    #[test]
+    fn hash_ignores_aslr_addresses() {
+        let hash = stack_hash(SYMBOLIZED, 5).unwrap();
+        assert_eq!(
+            stack_hash(&relocate(SYMBOLIZED, "0x55d0c1", "0x5612ab"), 5),
+            Some(hash)
+        );
+
+        let hash = stack_hash(UNSYMBOLIZED, 5).unwrap();
+        assert_eq!(
+            stack_hash(&relocate(UNSYMBOLIZED, "0x55d0c1", "0x5612ab"), 5),
+            Some(hash)
+        );
+    }
+
+    //solution_stacksのハッシュはスナップショットに残るので、Rustやlibaflを更新しても変わってはいけない
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(stack_hash(SYMBOLIZED, 5), Some(0x35ea_41f3_8eb6_d437));
+    }
+
+    //ランタイムのフレームを飛ばすので、abortを経由したかどうかでハッシュは変わらない
+    #[test]
+    fn runtime_frames_are_skipped() {
+        assert_eq!(stack_hash(ABORTED, 5), stack_hash(SYMBOLIZED, 5));
+        assert_eq!(stack_hash(ABORTED, 1), stack_hash(SYMBOLIZED, 1));
+
+        //libc.so.6のフレームだけを除くと、targetの2つのフレームが残る
+        let target_only = "\
+    #0 0x55d0c1a2b3c4  (/work/target+0x1234)
+    #1 0x55d0c1a2b5d6  (/work/target+0x1456)
+";
+        assert_eq!(stack_hash(UNSYMBOLIZED, 5), stack_hash(target_only, 5));
+    }
+
+    #[test]
+    fn runtime_frames_match_known_locations() {
+        for location in [
+            "in __asan_memcpy (/usr/lib/libasan.so.8+0xbd1c)",
+            "in __interceptor_strcpy",
+            "in raise ../sysdeps/posix/raise.c:26:13",
+            "(/lib/x86_64-linux-gnu/libc.so.6+0x18bd3e)",
+            "(/usr/lib/libstdc++.so.6+0xae4d0)",
+        ] {
+            assert!(
+                RUNTIME_FRAMES
+                    .iter()
+                    .any(|runtime| location.contains(runtime)),
+                "{location}"
+            );
+        }
+        //関数名にraiseやabortを含むだけのターゲットのフレームは残す
+        for location in [
+            "in raise_error /src/target.c:5:3",
+            "in abort_parse /src/target.c:9:3",
+        ] {
+            assert!(
+                !RUNTIME_FRAMES
+                    .iter()
+                    .any(|runtime| location.contains(runtime)),
+                "{location}"
+            );
+        }
+    }
+
+    //クラッシュした箇所のスタックだけを見て、allocated byなどの後のスタックは見ない
+    #[test]
+    fn only_the_first_stack_is_hashed() {
+        let other_allocation =
+            SYMBOLIZED.replace("main /src/target.c:25:17", "init /src/init.c:3:1");
+        assert_eq!(stack_hash(&other_allocation, 5), stack_hash(SYMBOLIZED, 5));
+
+        let other_caller =
+            SYMBOLIZED.replace("in parse /src/target.c:20:9", "in load /src/target.c:40:9");
+        assert_ne!(stack_hash(&other_caller, 5), stack_hash(SYMBOLIZED, 5));
+        //上から1フレームだけなら、呼び出し元の違いは見ない
+        assert_eq!(stack_hash(&other_caller, 1), stack_hash(SYMBOLIZED, 1));
+    }
+
+    fn new_state() -> RunnerState {
+        StdState::new(
+            StdRand::with_seed(0),
+            InMemoryCorpus::<BytesInput>::new(),
+            InMemoryCorpus::new(),
+            &mut ConstFeedback::new(false),
+            &mut ConstFeedback::new(false),
+        )
+        .unwrap()
+    }
+
+    fn post_exec(observer: &mut StackHashObserver, pid: i32, exit_kind: ExitKind) {
+        let mut state = new_state();
+        let input = BytesInput::new(vec![]);
+        Observer::<RunnerState>::pre_exec(observer, &mut state, &input).unwrap();
+        observer.set_pid(pid);
+        Observer::<RunnerState>::post_exec(observer, &mut state, &input, &exit_kind).unwrap();
+    }
+
+    //タイムアウトした実行が残したレポートは、次のクラッシュのハッシュにならない
+    #[test]
+    fn reports_are_read_only_for_the_current_child() {
+        let work_dir = WorkDir::new("stacktrace-test-pid").unwrap();
+        let log_dir = work_dir.path().join(".asan");
+        let mut observer = StackHashObserver::new(STACK_NAME, log_dir.clone(), 5).unwrap();
+
+        fs::write(log_dir.join("asan.100"), ABORTED).unwrap();
+        post_exec(&mut observer, 100, ExitKind::Timeout);
+        assert_eq!(observer.hash(), None);
+        assert_eq!(fs::read_dir(&log_dir).unwrap().count(), 0);
+
+        //別のプロセスのレポートは読まずに消す
+        fs::write(log_dir.join("asan.200"), UNSYMBOLIZED).unwrap();
+        fs::write(log_dir.join("asan.201"), SYMBOLIZED).unwrap();
+        post_exec(&mut observer, 201, ExitKind::Crash);
+        assert_eq!(observer.hash(), stack_hash(SYMBOLIZED, 5));
+        assert_eq!(fs::read_dir(&log_dir).unwrap().count(), 0);
+
+        fs::write(log_dir.join("asan.300"), SYMBOLIZED).unwrap();
+        post_exec(&mut observer, 301, ExitKind::Crash);
+        assert_eq!(observer.hash(), None);
+        assert_eq!(fs::read_dir(&log_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn report_without_frames_has_no_hash() {
+        assert_eq!(stack_hash("==1==ERROR: AddressSanitizer: SEGV\n", 5), None);
+        let runtime_only = "    #0 0x7f3a1c0c5d3e  (/lib/x86_64-linux-gnu/libc.so.6+0x18bd3e)\n";
+        assert_eq!(stack_hash(runtime_only, 5), None);
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::Write as _,
+    marker::PhantomData,
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
+
+use libafl::{
+    corpus::{Corpus, CorpusId},
+    feedbacks::MapFeedbackMetadata,
+    schedulers::minimizer::IsFavoredMetadata,
+    stages::{calibrate::UnstableEntriesMetadata, Stage},
+    state::{HasCorpus, HasExecutions, HasMetadata, HasNamedMetadata, HasStartTime, UsesState},
+    Error,
+};
+use libafl_bolts::current_time;
+
+use crate::{afl::Origin, config::TargetConfig, solutions::SolutionCounts, timeout::AutoTimeout};
+
+//afl-fuzzのSTATS_UPDATE_SECとPLOT_UPDATE_SECと同じ間隔
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+const PLOT_INTERVAL: Duration = Duration::from_secs(5);
+
+//afl-plotが読むplot_dataの見出し
+const PLOT_HEADER: &str = "# relative_time, cycles_done, cur_item, corpus_count, pending_total, pending_favs, map_size, saved_crashes, saved_hangs, max_depth, execs_per_sec, total_execs, edges_found\n";
+
+//fuzzer_statsとplot_dataに書き出す値
+struct Values {
+    run_time: Duration,
+    execs: u64,
+    execs_per_sec: f64,
+    corpus_count: usize,
+    corpus_favored: usize,
+    corpus_found: usize,
+    corpus_imported: usize,
+    pending_total: usize,
+    pending_favs: usize,
+    max_depth: usize,
+    cur_item: usize,
+    edges_found: usize,
+    unstable: Option<usize>,
+    crashes: u64,
+    hangs: u64,
+    slowest_exec: Duration,
+    timeout: Duration,
+}
+
+//AFL++のツール(afl-whatsup、afl-plotなど)が読むfuzzer_statsとplot_dataを、出力ディレクトリに書き出す
+//一巡したかどうかや、最後に見つけた時刻などは、ステージが呼ばれるたびに値の変化から調べる
+pub struct AflStatsStage<E, EM, Z> {
+    stats_path: PathBuf,
+    plot_path: PathBuf,
+    //MaxMapFeedbackのhistoryの名前
+    map_name: String,
+    map_size: usize,
+    timeout: Duration,
+    banner: String,
+    command_line: String,
+    cpu_affinity: Option<usize>,
+    last_stats: Option<Instant>,
+    last_plot: Option<Instant>,
+    last_corpus_idx: Option<CorpusId>,
+    cycles_done: u64,
+    corpus_count: usize,
+    crashes: u64,
+    hangs: u64,
+    //UNIX時刻の秒数で、0はまだないことを表す
+    last_find: u64,
+    last_crash: u64,
+    last_hang: u64,
+    execs_at_crash: u64,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, Z> AflStatsStage<E, EM, Z> {
+    //clientはLauncherのコア番号で、afl-fuzzのcpu_affinityとして書き出す
+    pub fn new(
+        stats_path: PathBuf,
+        plot_path: PathBuf,
+        map_name: &str,
+        target: &TargetConfig,
+        map_size: usize,
+        client: Option<usize>,
+    ) -> Self {
+        let banner = PathBuf::from(&target.program)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Self {
+            stats_path,
+            plot_path,
+            map_name: map_name.to_string(),
+            map_size,
+            timeout: target.timeout.initial(),
+            banner,
+            command_line: std::env::args().collect::<Vec<_>>().join(" "),
+            cpu_affinity: client,
+            last_stats: None,
+            last_plot: None,
+            last_corpus_idx: None,
+            cycles_done: 0,
+            corpus_count: 0,
+            crashes: 0,
+            hangs: 0,
+            last_find: 0,
+            last_crash: 0,
+            last_hang: 0,
+            execs_at_crash: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn write_stats(&self, values: &Values, start_time: Duration) -> Result<(), Error> {
+        let percent = |count: usize, total: usize| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f64 * 100.0 / total as f64
+            }
+        };
+        let stability = match values.unstable {
+            Some(unstable) => percent(
+                values.edges_found.saturating_sub(unstable),
+                values.edges_found,
+            ),
+            None => 100.0,
+        };
+
+        let fields: Vec<(&str, String)> = vec![
+            ("start_time", start_time.as_secs().to_string()),
+            ("last_update", current_time().as_secs().to_string()),
+            ("run_time", values.run_time.as_secs().to_string()),
+            ("fuzzer_pid", process::id().to_string()),
+            ("cycles_done", self.cycles_done.to_string()),
+            ("execs_done", values.execs.to_string()),
+            ("execs_per_sec", format!("{:.2}", values.execs_per_sec)),
+            ("corpus_count", values.corpus_count.to_string()),
+            ("corpus_favored", values.corpus_favored.to_string()),
+            ("corpus_found", values.corpus_found.to_string()),
+            ("corpus_imported", values.corpus_imported.to_string()),
+            ("max_depth", values.max_depth.to_string()),
+            ("cur_item", values.cur_item.to_string()),
+            ("pending_favs", values.pending_favs.to_string()),
+            ("pending_total", values.pending_total.to_string()),
+            ("stability", format!("{stability:.2}%")),
+            (
+                "bitmap_cvg",
+                format!("{:.2}%", percent(values.edges_found, self.map_size)),
+            ),
+            ("saved_crashes", values.crashes.to_string()),
+            ("saved_hangs", values.hangs.to_string()),
+            ("last_find", self.last_find.to_string()),
+            ("last_crash", self.last_crash.to_string()),
+            ("last_hang", self.last_hang.to_string()),
+            (
+                "execs_since_crash",
+                values.execs.saturating_sub(self.execs_at_crash).to_string(),
+            ),
+            ("exec_timeout", values.timeout.as_millis().to_string()),
+            (
+                "slowest_exec_ms",
+                values.slowest_exec.as_millis().to_string(),
+            ),
+            ("peak_rss_mb", peak_rss_mb().to_string()),
+            (
+                "cpu_affinity",
+                self.cpu_affinity
+                    .map_or_else(|| "-1".to_string(), |core| core.to_string()),
+            ),
+            ("edges_found", values.edges_found.to_string()),
+            ("total_edges", self.map_size.to_string()),
+            ("var_byte_count", values.unstable.unwrap_or(0).to_string()),
+            ("afl_banner", self.banner.clone()),
+            (
+                "afl_version",
+                format!("{}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            ),
+            ("target_mode", "default".to_string()),
+            ("command_line", self.command_line.clone()),
+        ];
+
+        //afl-fuzzと同じく、キーを18文字に揃える
+        let mut text = String::new();
+        for (key, value) in fields {
+            let _ = writeln!(text, "{key:<18}: {value}");
+        }
+
+        //afl-whatsupが書き込み途中のファイルを読まないように、renameで置き換える
+        let mut tmp_path = self.stats_path.clone();
+        tmp_path.set_extension("tmp");
+        fs::write(&tmp_path, text)?;
+        fs::rename(&tmp_path, &self.stats_path)?;
+        Ok(())
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn write_plot(&self, values: &Values) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.plot_path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(PLOT_HEADER.as_bytes())?;
+        }
+
+        let coverage = if self.map_size == 0 {
+            0.0
+        } else {
+            values.edges_found as f64 * 100.0 / self.map_size as f64
+        };
+        writeln!(
+            file,
+            "{}, {}, {}, {}, {}, {}, {:.2}%, {}, {}, {}, {:.2}, {}, {}",
+            values.run_time.as_secs(),
+            self.cycles_done,
+            values.cur_item,
+            values.corpus_count,
+            values.pending_total,
+            values.pending_favs,
+            coverage,
+            values.crashes,
+            values.hangs,
+            values.max_depth,
+            values.execs_per_sec,
+            values.execs,
+            values.edges_found,
+        )?;
+        Ok(())
+    }
+}
+
+impl<E, EM, Z> UsesState for AflStatsStage<E, EM, Z>
+where
+    E: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for AflStatsStage<E, EM, Z>
+where
+    E: UsesState,
+    E::State: HasCorpus + HasExecutions + HasMetadata + HasNamedMetadata + HasStartTime,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut Self::State,
+        _manager: &mut EM,
+        corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        //queueの先頭に戻ったら一巡したとみなす
+        if self.last_corpus_idx.is_some_and(|last| corpus_idx <= last) {
+            self.cycles_done += 1;
+        }
+        self.last_corpus_idx = Some(corpus_idx);
+
+        //初めて呼ばれたときは、シードや再起動前に見つけたものを新しい発見として数えない
+        let first = self.last_stats.is_none();
+        let now = current_time().as_secs();
+        let execs = *state.executions() as u64;
+        if state.corpus().count() > self.corpus_count {
+            self.corpus_count = state.corpus().count();
+            if !first {
+                self.last_find = now;
+            }
+        }
+        if let Ok(counts) = state.metadata::<SolutionCounts>() {
+            if counts.crashes > self.crashes && !first {
+                self.last_crash = now;
+                self.execs_at_crash = execs;
+            }
+            if counts.hangs > self.hangs && !first {
+                self.last_hang = now;
+            }
+            self.crashes = counts.crashes;
+            self.hangs = counts.hangs;
+        }
+
+        let stats = self
+            .last_stats
+            .is_none_or(|last| last.elapsed() >= STATS_INTERVAL);
+        let plot = self
+            .last_plot
+            .is_none_or(|last| last.elapsed() >= PLOT_INTERVAL);
+        if !stats && !plot {
+            return Ok(());
+        }
+
+        let values = self.values(state, corpus_idx)?;
+        if stats {
+            self.write_stats(&values, *state.start_time())?;
+            self.last_stats = Some(Instant::now());
+        }
+        if plot {
+            self.write_plot(&values)?;
+            self.last_plot = Some(Instant::now());
+        }
+        Ok(())
+    }
+}
+
+impl<E, EM, Z> AflStatsStage<E, EM, Z>
+where
+    E: UsesState,
+    E::State: HasCorpus + HasExecutions + HasMetadata + HasNamedMetadata + HasStartTime,
+{
+    #[allow(clippy::cast_precision_loss)]
+    fn values(&self, state: &E::State, corpus_idx: CorpusId) -> Result<Values, Error> {
+        let corpus = state.corpus();
+
+        let mut corpus_favored = 0;
+        let mut corpus_found = 0;
+        let mut corpus_imported = 0;
+        let mut pending_total = 0;
+        let mut pending_favs = 0;
+        let mut slowest_exec = Duration::ZERO;
+        //afl-fuzzと同じくシードを深さ1とし、親は必ず先にqueueに入っているので、idの順に深さが求まる
+        let mut depths = vec![1; corpus.last().map_or(0, |id| usize::from(id) + 1)];
+        for id in corpus.ids() {
+            let testcase = corpus.get(id)?.borrow();
+            let favored = testcase.has_metadata::<IsFavoredMetadata>();
+            let pending = testcase.scheduled_count() == 0;
+
+            corpus_favored += usize::from(favored);
+            pending_total += usize::from(pending);
+            pending_favs += usize::from(favored && pending);
+            if let Some(time) = testcase.exec_time() {
+                slowest_exec = slowest_exec.max(*time);
+            }
+            if testcase
+                .metadata_map()
+                .get::<Origin>()
+                .is_some_and(|origin| origin.sync.is_some())
+            {
+                corpus_imported += 1;
+            } else if let Some(parent) = testcase.parent_id() {
+                corpus_found += 1;
+                depths[usize::from(id)] =
+                    depths.get(usize::from(parent)).map_or(1, |depth| depth + 1);
+            }
+        }
+
+        let edges_found = state
+            .named_metadata_map()
+            .get::<MapFeedbackMetadata<u8>>(&self.map_name)
+            .map_or(0, |metadata| {
+                metadata
+                    .history_map
+                    .iter()
+                    .filter(|history| **history != 0)
+                    .count()
+            });
+
+        let run_time = current_time().saturating_sub(*state.start_time());
+        let execs = *state.executions() as u64;
+        let execs_per_sec = if run_time.is_zero() {
+            0.0
+        } else {
+            execs as f64 / run_time.as_secs_f64()
+        };
+
+        Ok(Values {
+            run_time,
+            execs,
+            execs_per_sec,
+            corpus_count: corpus.count(),
+            corpus_favored,
+            corpus_found,
+            corpus_imported,
+            pending_total,
+            pending_favs,
+            max_depth: depths.iter().copied().max().unwrap_or(0),
+            cur_item: usize::from(corpus_idx),
+            edges_found,
+            unstable: state
+                .metadata_map()
+                .get::<UnstableEntriesMetadata>()
+                .map(|metadata| metadata.unstable_entries().len()),
+            crashes: self.crashes,
+            hangs: self.hangs,
+            slowest_exec,
+            timeout: state
+                .metadata_map()
+                .get::<AutoTimeout>()
+                .map_or(self.timeout, |auto| auto.timeout),
+        })
+    }
+}
+
+//afl-fuzzと同じく、getrusageで測ったfuzzer自身の最大RSS
+fn peak_rss_mb() -> i64 {
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return 0;
+    }
+    usage.ru_maxrss / 1024
+}
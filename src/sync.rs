@@ -0,0 +1,347 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use libafl::{
+    corpus::{Corpus, CorpusId},
+    fuzzer::Evaluator,
+    inputs::{Input, UsesInput},
+    stages::Stage,
+    state::{HasCorpus, HasMetadata, UsesState},
+    Error,
+};
+use libafl_bolts::impl_serdeany;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    afl::{self, Origin},
+    config::SyncConfig,
+};
+
+//取り込んでいる最中のAFL++のインスタンスで、stateに置いておきOriginに写す
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSource {
+    //出力ディレクトリの名前で、afl-fuzzの-Sに渡したもの
+    pub peer: String,
+    //相手のqueueでのid
+    pub id: Option<usize>,
+}
+
+impl_serdeany!(SyncSource);
+
+//クライアントが再起動されても、取り込み済みのファイルや書き出し済みのqueueを繰り返さない
+//プロセスを止めて再開したときは、スナップショットから戻す
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SyncProgress {
+    //ピアのqueueごとの、取り込んだファイルの最新の更新時刻
+    pub last_times: BTreeMap<PathBuf, SystemTime>,
+    //最後に書き出したqueueのテストケース
+    pub exported: Option<CorpusId>,
+}
+
+impl_serdeany!(SyncProgress);
+
+struct Peer {
+    name: String,
+    queue_dir: PathBuf,
+}
+
+//afl-fuzzのsync_fuzzersと同じく、一定時間ごとにAFL++のインスタンスのqueueから新しいファイルを取り込む
+//取り込むのはqueue/の直下のファイルだけで、.stateなどの隠しディレクトリは読まない
+//(SyncFromDiskStageはサブディレクトリも読むので使わない)
+//取り込んだファイルは普通の入力と同じくHitcountsMapObserverで評価し、新しいカバレッジだけがqueueに入る
+pub struct AflSyncStage<E, EM, Z> {
+    peers: Vec<Peer>,
+    interval: Duration,
+    //書き出し先のqueue/と、次に振るid
+    export: Option<(PathBuf, usize)>,
+    //再開したときは、書き出し済みのものを書き出し先のファイルから調べ直す
+    resumed: bool,
+    last_sync: Option<Instant>,
+    phantom: PhantomData<(E, EM, Z)>,
+}
+
+impl<E, EM, Z> AflSyncStage<E, EM, Z>
+where
+    E: UsesState<State = Z::State>,
+    EM: UsesState<State = Z::State>,
+    Z: Evaluator<E, EM>,
+    Z::State: HasCorpus + HasMetadata,
+{
+    //enabledがfalseのクライアントは何もしない(Launcherでは1つのクライアントだけがやり取りする)
+    pub fn new(config: &SyncConfig, enabled: bool, resumed: bool) -> Result<Self, Error> {
+        let peers = config
+            .peers
+            .iter()
+            .filter(|_| enabled)
+            .map(|dir| Peer {
+                name: dir.file_name().map_or_else(
+                    || dir.display().to_string(),
+                    |name| name.to_string_lossy().into_owned(),
+                ),
+                queue_dir: dir.join("queue"),
+            })
+            .collect();
+
+        let export = match &config.export {
+            Some(dir) if enabled => {
+                let queue_dir = dir.join("queue");
+                //afl-fuzz -Sのインスタンスと同じ形にする(is_main_nodeは置かない)
+                //AFL++の-Mが取り込み、セカンダリ(-S)には-Mを経由して届く
+                fs::create_dir_all(&queue_dir)?;
+                //ピアは取り込んだidを覚えているので、前回より大きいidから振る
+                let next_id = afl::next_id(&queue_dir)?;
+                Some((queue_dir, next_id))
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            peers,
+            interval: config.interval,
+            export,
+            resumed,
+            last_sync: None,
+            phantom: PhantomData,
+        })
+    }
+
+    fn import(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Z::State,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        for peer in &self.peers {
+            //相手がまだ起動していないときは、次の機会に取り込む
+            if !peer.queue_dir.is_dir() {
+                continue;
+            }
+
+            let last_time = state
+                .metadata::<SyncProgress>()?
+                .last_times
+                .get(&peer.queue_dir)
+                .copied();
+            let files = new_files(&peer.queue_dir, last_time)?;
+
+            for (path, _) in &files {
+                state.add_metadata(SyncSource {
+                    peer: peer.name.clone(),
+                    id: path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .and_then(afl::parse_id),
+                });
+                let result = <Z::State as UsesInput>::Input::from_file(path)
+                    .and_then(|input| fuzzer.evaluate_input(state, executor, manager, input));
+                let _ = state.metadata_map_mut().remove::<SyncSource>();
+                result?;
+            }
+
+            //idの順に取り込むので、更新時刻の順とは限らない
+            if let Some(time) = files.iter().map(|(_, time)| *time).max() {
+                state
+                    .metadata_mut::<SyncProgress>()?
+                    .last_times
+                    .insert(peer.queue_dir.clone(), time);
+            }
+        }
+        Ok(())
+    }
+
+    //queueのファイル名のidだけを振り直して書き出す
+    //取り込んだものは相手が持っているので書き出さない
+    fn export(&mut self, state: &mut Z::State) -> Result<(), Error> {
+        let Some((queue_dir, next_id)) = &mut self.export else {
+            return Ok(());
+        };
+
+        let mut exported = state.metadata::<SyncProgress>()?.exported;
+        loop {
+            let next = match exported {
+                Some(id) => state.corpus().next(id),
+                None => state.corpus().first(),
+            };
+            let Some(id) = next else {
+                break;
+            };
+            exported = Some(id);
+
+            let testcase = state.corpus().get(id)?.borrow();
+            if testcase
+                .metadata_map()
+                .get::<Origin>()
+                .is_some_and(|origin| origin.sync.is_some())
+            {
+                continue;
+            }
+            let Some(input) = testcase.input() else {
+                continue;
+            };
+
+            let rest = testcase.filename().as_deref().and_then(rest).unwrap_or("");
+            //一時ファイルに書いてからrenameするので、書き込み途中のファイルは読まれない
+            input.to_file(queue_dir.join(format!("id:{next_id:06}{rest}")))?;
+            *next_id += 1;
+        }
+
+        state.metadata_mut::<SyncProgress>()?.exported = exported;
+        Ok(())
+    }
+
+    //書き出したファイルは名前のidだけが違うので、残りが一致するqueueのテストケースのうち最後のもの
+    //スナップショットは一定時間ごとなので、その後に書き出したものをもう一度書き出さないように数え直す
+    fn last_exported(&self, state: &Z::State) -> Result<Option<CorpusId>, Error> {
+        let Some((queue_dir, _)) = &self.export else {
+            return Ok(None);
+        };
+
+        let mut names = HashSet::new();
+        for entry in fs::read_dir(queue_dir)? {
+            if let Some(name) = entry?.file_name().to_str().and_then(rest) {
+                names.insert(name.to_string());
+            }
+        }
+
+        let mut last = None;
+        let mut id = state.corpus().first();
+        while let Some(current) = id {
+            let testcase = state.corpus().get(current)?.borrow();
+            if testcase
+                .filename()
+                .as_deref()
+                .and_then(rest)
+                .is_some_and(|name| names.contains(name))
+            {
+                last = Some(current);
+            }
+            id = state.corpus().next(current);
+        }
+        Ok(last)
+    }
+}
+
+//queueのファイル名から、idの後ろの部分(,src:000001,time:...)を取り出す
+fn rest(name: &str) -> Option<&str> {
+    afl::parse_id(name)?;
+    name.get("id:000000".len()..)
+}
+
+impl<E, EM, Z> UsesState for AflSyncStage<E, EM, Z>
+where
+    E: UsesState,
+    Z: UsesState,
+{
+    type State = E::State;
+}
+
+impl<E, EM, Z> Stage<E, EM, Z> for AflSyncStage<E, EM, Z>
+where
+    E: UsesState<State = Z::State>,
+    EM: UsesState<State = Z::State>,
+    Z: Evaluator<E, EM>,
+    Z::State: HasCorpus + HasMetadata,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut Self::State,
+        manager: &mut EM,
+        _corpus_idx: CorpusId,
+    ) -> Result<(), Error> {
+        if self.peers.is_empty() && self.export.is_none() {
+            return Ok(());
+        }
+        if self
+            .last_sync
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return Ok(());
+        }
+        self.last_sync = Some(Instant::now());
+
+        if !state.has_metadata::<SyncProgress>() {
+            state.add_metadata(SyncProgress::default());
+        }
+        if self.resumed {
+            self.resumed = false;
+            let exported = self.last_exported(state)?;
+            state.metadata_mut::<SyncProgress>()?.exported = exported;
+        }
+
+        self.export(state)?;
+
+        //取り込んだものはqueueのテストケースから作ったものではないので、親を付けない
+        let current = state.corpus_mut().current_mut().take();
+        let result = self.import(fuzzer, executor, state, manager);
+        *state.corpus_mut().current_mut() = current;
+        result
+    }
+}
+
+//queue/の直下にある、last_timeより後に更新された空でないファイルを、afl-fuzzと同じくidの順に並べる
+//隠しファイルと.stateなどのディレクトリは飛ばす
+fn new_files(
+    queue_dir: &Path,
+    last_time: Option<SystemTime>,
+) -> Result<Vec<(PathBuf, SystemTime)>, Error> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(queue_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        //書き込み途中で消されたものなどは、読めなければ飛ばす
+        let Ok(metadata) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        let Ok(time) = metadata.modified() else {
+            continue;
+        };
+        if !metadata.is_file() || metadata.len() == 0 || last_time.is_some_and(|last| time <= last)
+        {
+            continue;
+        }
+        files.push((entry.path(), time));
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::WorkDir;
+
+    //AFL++のqueue/の直下のファイルだけをidの順に読み、.state/の下や隠しファイル、空のファイルは読まない
+    #[test]
+    fn only_top_level_queue_files_are_imported() {
+        let work_dir = WorkDir::new("sync-test").unwrap();
+        let queue_dir = work_dir.path().join("queue");
+        fs::create_dir_all(queue_dir.join(".state/auto_extras")).unwrap();
+        fs::write(queue_dir.join(".state/auto_extras/auto_000000"), "AUTO").unwrap();
+        fs::write(queue_dir.join(".id:000003,sync:sec2"), "C").unwrap();
+        fs::write(queue_dir.join("id:000002,src:000001"), "").unwrap();
+        fs::write(queue_dir.join("id:000001,src:000000"), "B").unwrap();
+        fs::write(queue_dir.join("id:000000,orig:seed"), "A").unwrap();
+
+        let files = new_files(&queue_dir, None).unwrap();
+        let names = files
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["id:000000,orig:seed", "id:000001,src:000000"]);
+
+        //取り込んだ時刻より後に更新されたものだけを読む
+        let last_time = files.iter().map(|(_, time)| *time).max();
+        assert!(new_files(&queue_dir, last_time).unwrap().is_empty());
+    }
+}
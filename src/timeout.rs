@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use libafl::{corpus::Corpus, state::HasCorpus, Error};
+use libafl_bolts::impl_serdeany;
+use serde::{Deserialize, Serialize};
+
+//afl-fuzzと同じく、20ms単位に切り上げる
+const ROUND: Duration = Duration::from_millis(20);
+
+//autoで求めたタイムアウトで、solutionのサイドカーに書き出すためにstateに置く
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AutoTimeout {
+    pub timeout: Duration,
+}
+
+impl_serdeany!(AutoTimeout);
+
+//afl-fuzzの-tを省略したときと同じく、コーパスの実行時間からタイムアウトを求める
+//平均のmultiplier倍と、一番遅いテストケースの実行時間の大きい方を使い、maxで抑える
+//実行時間はTimeFeedbackがテストケースに付けたもので、シードを読み込んだ後に呼ぶ
+pub fn from_corpus<S>(state: &S, multiplier: f64, max: Duration) -> Result<Duration, Error>
+where
+    S: HasCorpus,
+{
+    let mut times = Vec::new();
+    for id in state.corpus().ids() {
+        if let Some(time) = *state.corpus().get(id)?.borrow().exec_time() {
+            times.push(time);
+        }
+    }
+
+    let Some(slowest) = times.iter().max() else {
+        return Ok(max);
+    };
+    let average = times.iter().sum::<Duration>() / times.len() as u32;
+
+    let timeout = average.mul_f64(multiplier).max(*slowest);
+    let rounds = timeout.as_nanos().div_ceil(ROUND.as_nanos()).max(1);
+    Ok((ROUND * rounds as u32).min(max))
+}
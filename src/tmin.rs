@@ -0,0 +1,160 @@
+use std::{fmt::Debug, marker::PhantomData};
+
+use libafl::{
+    corpus::{Corpus, Testcase},
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::{Feedback, FeedbackFactory},
+    inputs::{BytesInput, HasBytesVec, Input, UsesInput},
+    mutators::{havoc_mutations, StdScheduledMutator},
+    observers::ObserversTuple,
+    stages::{Stage, StdTMinMutationalStage},
+    state::{HasClientPerfMonitor, HasCorpus},
+    Error,
+};
+use libafl_bolts::{tuples::MatchName, Named};
+
+use crate::{
+    cli::TminOptions,
+    config::ToolConfig,
+    executor::{SignalObserver, SIGNAL_NAME},
+    runner::{self, Runner, WorkDir},
+};
+
+//afl-tminの代わりに、ファジングと同じforkserverの設定でクラッシュする入力を小さくする
+//StdTMinMutationalStageは、入力を短くするmutationのうち、元と同じクラッシュになるものだけを採用する
+pub fn tmin(config: &ToolConfig, options: &TminOptions) -> Result<(), Error> {
+    let input = BytesInput::from_file(&options.input)?;
+    let work_dir = WorkDir::new("tmin")?;
+    let mut shmem = runner::coverage_shmem(config, &work_dir)?;
+    let mut runner = Runner::new(config, &mut shmem, &work_dir)?;
+
+    //元の入力がクラッシュしなければ、保つべきクラッシュがない
+    let execution = runner.run(&input)?;
+    if execution.exit_kind != ExitKind::Crash {
+        return Err(Error::illegal_argument(format!(
+            "{} does not crash the target (exit kind: {:?})",
+            options.input.display(),
+            execution.exit_kind
+        )));
+    }
+
+    if options.stack_hash && execution.stack_hash.is_none() {
+        return Err(Error::illegal_argument(
+            "--stack-hash needs an ASan report; build the target with -fsanitize=address",
+        ));
+    }
+
+    //空の入力はこれ以上小さくできない
+    let minimized = if input.bytes().is_empty() {
+        input.clone()
+    } else {
+        let id = runner
+            .state
+            .corpus_mut()
+            .add(Testcase::new(input.clone()))?;
+        let mutator = StdScheduledMutator::new(havoc_mutations());
+        let factory = SameCrashFactory::new(options.stack_hash);
+        //StdTMinMutationalStageは、小さくなったクラッシュを見つけるたびに回数を0から数え直す
+        //そのため--runsは合計の実行回数ではなく、連続して失敗した回数の上限になる
+        let mut stage = StdTMinMutationalStage::new(mutator, factory, options.runs);
+        stage.perform(
+            &mut runner.fuzzer,
+            &mut runner.executor,
+            &mut runner.state,
+            &mut runner.manager,
+            id,
+        )?;
+        runner.state.corpus().cloned_input_for_id(id)?
+    };
+
+    minimized.to_file(&options.output)?;
+    println!(
+        "minimized {} from {} to {} bytes: {}",
+        options.input.display(),
+        input.bytes().len(),
+        minimized.bytes().len(),
+        options.output.display()
+    );
+    Ok(())
+}
+
+//元の入力と同じシグナルで、指定があれば同じスタックのハッシュでクラッシュした実行だけを採用する
+#[derive(Debug)]
+pub struct SameCrashFeedback<S> {
+    signal: Option<i32>,
+    stack_hash: Option<u64>,
+    phantom: PhantomData<S>,
+}
+
+impl<S> Named for SameCrashFeedback<S> {
+    fn name(&self) -> &str {
+        "SameCrashFeedback"
+    }
+}
+
+impl<S> Feedback<S> for SameCrashFeedback<S>
+where
+    S: UsesInput + HasClientPerfMonitor + Debug,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        if *exit_kind != ExitKind::Crash {
+            return Ok(false);
+        }
+
+        let signal = observers
+            .match_name::<SignalObserver>(SIGNAL_NAME)
+            .and_then(SignalObserver::signal);
+        if signal != self.signal {
+            return Ok(false);
+        }
+
+        Ok(match self.stack_hash {
+            Some(hash) => runner::stack_hash(observers) == Some(hash),
+            None => true,
+        })
+    }
+}
+
+//StdTMinMutationalStageは元の入力を実行した直後のobserverを渡すので、そこから元のクラッシュを読む
+#[derive(Debug)]
+pub struct SameCrashFactory {
+    stack_hash: bool,
+}
+
+impl SameCrashFactory {
+    pub fn new(stack_hash: bool) -> Self {
+        Self { stack_hash }
+    }
+}
+
+impl<S, OT> FeedbackFactory<SameCrashFeedback<S>, S, OT> for SameCrashFactory
+where
+    S: UsesInput + HasClientPerfMonitor + Debug,
+    OT: MatchName,
+{
+    fn create_feedback(&self, observers: &OT) -> SameCrashFeedback<S> {
+        SameCrashFeedback {
+            signal: observers
+                .match_name::<SignalObserver>(SIGNAL_NAME)
+                .and_then(SignalObserver::signal),
+            stack_hash: if self.stack_hash {
+                runner::stack_hash(observers)
+            } else {
+                None
+            },
+            phantom: PhantomData,
+        }
+    }
+}
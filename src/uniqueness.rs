@@ -0,0 +1,308 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    path::PathBuf,
+};
+
+use libafl::{
+    corpus::{Corpus, Testcase},
+    events::{EventFirer, NopEventManager},
+    executors::ExitKind,
+    feedbacks::{Feedback, MaxMapFeedback, NewHashFeedbackMetadata},
+    inputs::UsesInput,
+    observers::{MapObserver, ObserverWithHashField, ObserversTuple},
+    state::{HasClientPerfMonitor, HasExecutions, HasMetadata, HasNamedMetadata, HasSolutions},
+    Error,
+};
+use libafl_bolts::{impl_serdeany, AsIter, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::UniquenessPolicy,
+    solutions::SolutionKind,
+    stacktrace::{self, StackHashObserver},
+};
+
+//new-coverageで比較する、これまでのsolutionのカバレッジ
+//クラッシュとハングで別に持つので、クラッシュと同じエッジを通るハングも保存される(AFLのvirgin_crashとvirgin_tmoutと同じ)
+pub const CRASH_HISTORY_NAME: &str = "solutions_crash";
+pub const HANG_HISTORY_NAME: &str = "solutions_hang";
+//stack-hashで比較する、これまでのsolutionのスタックのハッシュ
+pub const STACK_HISTORY_NAME: &str = "solution_stacks";
+
+//solutionがどの基準でユニークと判断されたか
+//ハングやASanのレポートがないクラッシュにはスタックがないので、stack-hashのときはnew-coverageで判断する
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UniquenessMetadata {
+    pub policy: UniquenessPolicy,
+    //coverage-hashならカバレッジマップ全体の、stack-hashならスタックのハッシュ
+    pub hash: Option<u64>,
+}
+
+impl_serdeany!(UniquenessMetadata);
+
+//これまでに見たカバレッジマップ全体のハッシュ
+//new-coverageのhistoryと同じく、クラッシュとハングで別に持つ
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CoverageHashes {
+    pub crashes: HashSet<u64>,
+    pub hangs: HashSet<u64>,
+}
+
+impl_serdeany!(CoverageHashes);
+
+impl CoverageHashes {
+    pub fn hashes_mut(&mut self, kind: SolutionKind) -> &mut HashSet<u64> {
+        match kind {
+            SolutionKind::Crash => &mut self.crashes,
+            SolutionKind::Hang => &mut self.hangs,
+        }
+    }
+}
+
+//stack-hashで、バケット(スタックのハッシュ)ごとに最初に保存したsolutionの名前
+//重複したヒットは、このsolutionの.<名前>.hitsに記録する
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StackBuckets {
+    pub solutions: HashMap<u64, String>,
+}
+
+impl_serdeany!(StackBuckets);
+
+//クラッシュ・ハングをsolutionとして残すかどうかを、起動時に選んだ基準で判断する
+#[derive(Debug)]
+pub struct UniquenessFeedback<O, S> {
+    policy: UniquenessPolicy,
+    map_name: String,
+    stack_name: String,
+    crash_coverage: MaxMapFeedback<O, S, u8>,
+    hang_coverage: MaxMapFeedback<O, S, u8>,
+    //stack-hashのとき、重複したヒットの記録を置くディレクトリ
+    crashes_dir: PathBuf,
+    last: Option<(SolutionKind, UniquenessMetadata)>,
+}
+
+impl<O, S> UniquenessFeedback<O, S>
+where
+    O: MapObserver<Entry = u8> + for<'it> AsIter<'it, Item = u8>,
+    S: UsesInput + HasNamedMetadata + HasClientPerfMonitor + Debug,
+{
+    pub fn new(
+        policy: UniquenessPolicy,
+        map_observer: &O,
+        stack_observer: &StackHashObserver,
+        crashes_dir: PathBuf,
+    ) -> Self {
+        Self {
+            policy,
+            map_name: map_observer.name().to_string(),
+            stack_name: stack_observer.name().to_string(),
+            //コーパスのMaxMapFeedbackとは別に、solutionになったものだけのhistoryを持つ
+            crash_coverage: MaxMapFeedback::with_name(CRASH_HISTORY_NAME, map_observer),
+            hang_coverage: MaxMapFeedback::with_name(HANG_HISTORY_NAME, map_observer),
+            crashes_dir,
+            last: None,
+        }
+    }
+
+    fn new_coverage(&mut self, kind: SolutionKind) -> &mut MaxMapFeedback<O, S, u8> {
+        match kind {
+            SolutionKind::Crash => &mut self.crash_coverage,
+            SolutionKind::Hang => &mut self.hang_coverage,
+        }
+    }
+}
+
+impl<O, S> UniquenessFeedback<O, S>
+where
+    S: UsesInput + HasExecutions + HasMetadata + HasSolutions,
+{
+    //solutionの名前はAFL++の形式のままにして、バケットの最初のsolutionを探して紐付ける
+    //solutionsから消されたなどで見つからなければ、記録しない
+    fn record_hit(&self, state: &mut S, hash: u64) -> Result<(), Error> {
+        let known = state
+            .metadata::<StackBuckets>()?
+            .solutions
+            .get(&hash)
+            .cloned();
+        let name = match known {
+            Some(name) => name,
+            None => {
+                let Some(name) = find_bucket(state.solutions(), hash)? else {
+                    return Ok(());
+                };
+                state
+                    .metadata_mut::<StackBuckets>()?
+                    .solutions
+                    .insert(hash, name.clone());
+                name
+            }
+        };
+
+        let hits = self.crashes_dir.join(format!(".{name}.hits"));
+        stacktrace::record_hit(&hits, *state.executions())
+    }
+}
+
+//stack-hashで保存されたsolutionのうち、スタックのハッシュが一致するものの名前
+fn find_bucket<C: Corpus>(solutions: &C, hash: u64) -> Result<Option<String>, Error> {
+    let mut id = solutions.first();
+    while let Some(current) = id {
+        let testcase = solutions.get(current)?.borrow();
+        let matches = testcase
+            .metadata_map()
+            .get::<UniquenessMetadata>()
+            .is_some_and(|metadata| {
+                metadata.policy == UniquenessPolicy::StackHash && metadata.hash == Some(hash)
+            });
+        if matches {
+            return Ok(testcase.filename().clone());
+        }
+        id = solutions.next(current);
+    }
+    Ok(None)
+}
+
+impl<O, S> Named for UniquenessFeedback<O, S> {
+    fn name(&self) -> &str {
+        "UniquenessFeedback"
+    }
+}
+
+impl<O, S> Feedback<S> for UniquenessFeedback<O, S>
+where
+    O: MapObserver<Entry = u8> + for<'it> AsIter<'it, Item = u8>,
+    S: UsesInput
+        + HasClientPerfMonitor
+        + HasExecutions
+        + HasMetadata
+        + HasNamedMetadata
+        + HasSolutions
+        + Debug,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        self.crash_coverage.init_state(state)?;
+        self.hang_coverage.init_state(state)?;
+        if !state.has_named_metadata::<NewHashFeedbackMetadata>(STACK_HISTORY_NAME) {
+            state.add_named_metadata(NewHashFeedbackMetadata::default(), STACK_HISTORY_NAME);
+        }
+        if !state.has_metadata::<CoverageHashes>() {
+            state.add_metadata(CoverageHashes::default());
+        }
+        if !state.has_metadata::<StackBuckets>() {
+            state.add_metadata(StackBuckets::default());
+        }
+        Ok(())
+    }
+
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        //ハッシュはappend_metadataで覚えるので、後ろのfeedbackでsolutionにならなかったものは次も比べ直す
+        //objectiveの前段でクラッシュかハングだけに絞っている
+        let Some(kind) = SolutionKind::from_exit_kind(exit_kind) else {
+            return Ok(false);
+        };
+        let stack_hash = observers
+            .match_name::<StackHashObserver>(&self.stack_name)
+            .ok_or_else(|| Error::key_not_found("stack hash observer not found"))?
+            .hash();
+
+        let policy = match (self.policy, stack_hash) {
+            (UniquenessPolicy::StackHash, None) => UniquenessPolicy::NewCoverage,
+            (policy, _) => policy,
+        };
+
+        let (interesting, hash) = match policy {
+            UniquenessPolicy::All => (true, None),
+            UniquenessPolicy::NewCoverage => {
+                //MaxMapFeedbackはhistoryの埋まり具合をhistoryの名前でmonitorに送るが、
+                //カバレッジではなくsolutionの数と紛らわしいので送らない(数はSolutionKindFeedbackが送る)
+                let interesting = self.new_coverage(kind).is_interesting(
+                    state,
+                    &mut NopEventManager::new(),
+                    input,
+                    observers,
+                    exit_kind,
+                )?;
+                (interesting, None)
+            }
+            UniquenessPolicy::CoverageHash => {
+                let hash = observers
+                    .match_name::<O>(&self.map_name)
+                    .ok_or_else(|| Error::key_not_found("coverage map observer not found"))?
+                    .hash();
+                let interesting = !state
+                    .metadata_mut::<CoverageHashes>()?
+                    .hashes_mut(kind)
+                    .contains(&hash);
+                (interesting, Some(hash))
+            }
+            UniquenessPolicy::StackHash => {
+                let interesting = stack_hash.is_some_and(|hash| {
+                    state
+                        .named_metadata::<NewHashFeedbackMetadata>(STACK_HISTORY_NAME)
+                        .is_ok_and(|history| !history.hash_set.contains(&hash))
+                });
+                if let (false, Some(hash)) = (interesting, stack_hash) {
+                    self.record_hit(state, hash)?;
+                }
+                (interesting, stack_hash)
+            }
+        };
+
+        self.last = interesting.then_some((kind, UniquenessMetadata { policy, hash }));
+        Ok(interesting)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        if let Some((kind, metadata)) = self.last.take() {
+            match (metadata.policy, metadata.hash) {
+                //MaxMapFeedbackはappend_metadataでhistoryを更新するので、呼ばないと同じカバレッジのものが何度も保存される
+                (UniquenessPolicy::NewCoverage, _) => {
+                    self.new_coverage(kind)
+                        .append_metadata(state, observers, testcase)?;
+                }
+                (UniquenessPolicy::CoverageHash, Some(hash)) => {
+                    state
+                        .metadata_mut::<CoverageHashes>()?
+                        .hashes_mut(kind)
+                        .insert(hash);
+                }
+                (UniquenessPolicy::StackHash, Some(hash)) => {
+                    state
+                        .named_metadata_map_mut()
+                        .get_mut::<NewHashFeedbackMetadata>(STACK_HISTORY_NAME)
+                        .ok_or_else(|| Error::key_not_found("stack hash history not found"))?
+                        .hash_set
+                        .insert(hash);
+                }
+                _ => {}
+            }
+            testcase.add_metadata(metadata);
+        }
+        Ok(())
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.last = None;
+        Ok(())
+    }
+}